@@ -4,6 +4,8 @@ export type Handle = bigint;
 const symbols = {
   serial_err_len: { parameters: [], result: "usize" },
   serial_err_fill: { parameters: ["pointer", "usize"], result: "void" },
+  serial_err_json: { parameters: [], result: "pointer" },
+  serial_io_err_json: { parameters: ["u64", "i32"], result: "pointer" },
 
   serial_open: {
     parameters: [
@@ -20,8 +22,36 @@ const symbols = {
     ],
     result: "i32",
   },
+  // serial_open plus an out: u64[1] handle
+  serial_open2: {
+    parameters: [
+      "pointer",
+      "u32",
+      "u8",
+      "u8",
+      "u8",
+      "u8",
+      "u8",
+      "u8",
+      "u8",
+      "i32",
+      "buffer",
+    ],
+    result: "i32",
+  },
+  // Versioned JSON options (see src/config.rs); preferred over serial_open
+  serial_open_ex: {
+    parameters: ["buffer", "buffer"], // NUL-terminated JSON, out: u64[1]
+    result: "i32",
+  },
+  /**
+   * @deprecated Racy between threads; use `serial_open_ex` or `serial_open2`,
+   * which return the handle through an out-pointer.
+   */
   serial_last_handle: { parameters: [], result: "u64" },
   serial_close: { parameters: ["u64"], result: "i32" },
+  // which: 1=read, 2=write, 4=wait_event (bitmask)
+  serial_cancel: { parameters: ["u64", "i32"], result: "i32" },
 
   serial_write: {
     // Use 'buffer' so callers can pass a Uint8Array directly.
@@ -30,23 +60,125 @@ const symbols = {
     nonblocking: true,
   },
   serial_read: {
-    // Use 'buffer' so we can read into a Uint8Array directly.
+    // Use 'buffer' so we can read into a Uint8Array directly. A timeout >= 0
+    // is kept as the handle's read timeout; the other reads use it once.
     parameters: ["u64", "buffer", "usize", "i32"],
     result: "isize",
     nonblocking: true,
   },
+  // Overall deadline; on failure the bytes read so far stay for the next read
+  serial_read_exact: {
+    parameters: ["u64", "buffer", "usize", "i32"],
+    result: "isize",
+    nonblocking: true,
+  },
+  serial_read_until: {
+    parameters: ["u64", "buffer", "usize", "buffer", "usize", "i32"], // delim, buf
+    result: "isize",
+    nonblocking: true,
+  },
+  serial_read_frame: {
+    // buf, len, gap_us, gap_chars, timeout_ms, out: u64[1] first-byte ns
+    parameters: ["u64", "buffer", "usize", "u32", "f64", "i32", "buffer"],
+    result: "isize",
+    nonblocking: true,
+  },
+  serial_read_ts: {
+    // buf, len, timeout_ms, split, out: u64[1] arrival ns
+    parameters: ["u64", "buffer", "usize", "i32", "i32", "buffer"],
+    result: "isize",
+    nonblocking: true,
+  },
+  // Read-ahead buffer: peek never waits, so these run synchronously
+  serial_peek: { parameters: ["u64", "buffer", "usize"], result: "isize" },
+  serial_consume: { parameters: ["u64", "usize"], result: "isize" },
+  serial_unread: { parameters: ["u64", "buffer", "usize"], result: "i32" },
+
+  // capacity 0 turns buffering off (Linux only)
+  serial_set_rx_buffer: { parameters: ["u64", "usize"], result: "i32" },
+  // Callback per `dataCallback` below; null unregisters (Linux only)
+  serial_set_data_callback: {
+    parameters: ["u64", "function", "pointer"],
+    result: "i32",
+  },
+  serial_rx_stats: {
+    parameters: ["u64", "buffer", "buffer"], // out: usize[1], u64[1]
+    result: "i32",
+  },
 
   serial_set_lines: { parameters: ["u64", "i32", "i32", "i32"], result: "i32" },
   serial_get_lines: { parameters: ["u64", "buffer"], result: "i32" }, // out: u32[1] mask
 
+  serial_wait_event: {
+    parameters: ["u64", "u32", "i32", "buffer"], // out: u32[1] fired events
+    result: "i32",
+    nonblocking: true,
+  },
+
+  // JSON in (NUL-terminated) / JSON out (free with serial_free_cstr)
+  serial_set_config: { parameters: ["u64", "buffer"], result: "i32" },
+  serial_get_config: { parameters: ["u64"], result: "pointer" },
+  // action: 0 suspend output, 1 resume output, 2 send XOFF, 3 send XON
+  serial_tcflow: { parameters: ["u64", "i32"], result: "i32" },
+
   serial_flush: { parameters: ["u64", "i32", "i32"], result: "i32" },
   serial_drain: { parameters: ["u64"], result: "i32" },
+  // Drains, then break for break_us and mark for mab_us (microseconds)
+  serial_send_break: {
+    parameters: ["u64", "u32", "u32"],
+    result: "i32",
+    nonblocking: true,
+  },
 
   serial_list_ports_len: { parameters: [], result: "usize" },
   serial_list_ports_fill: { parameters: ["pointer", "usize"], result: "usize" },
   serial_free_cstr: { parameters: ["pointer"], result: "void" },
 } as const;
 
+/**
+ * Signature of the `serial_set_data_callback` callback:
+ * (user_data, kind, value, data, len). kind 0 = data chunk, 1 = fired event
+ * bits in `value`, 2 = last call (`value` 0 or the error code).
+ */
+export const dataCallback = {
+  parameters: ["pointer", "u32", "i32", "pointer", "usize"],
+  result: "void",
+} as const;
+
+/** Negative return codes, one per error kind. Mirrors src/error.rs. */
+export const ErrorCode = {
+  Io: -1,
+  NotFound: -2,
+  PermissionDenied: -3,
+  Busy: -4,
+  Disconnected: -5,
+  TimedOut: -6,
+  InvalidConfig: -7,
+  Unsupported: -8,
+  InvalidHandle: -9,
+  StaleHandle: -10,
+  Cancelled: -11,
+  WouldBlock: -12,
+} as const;
+export type ErrorKind = keyof typeof ErrorCode;
+
+/** Machine-readable error as reported by `serial_err_json`. */
+export type ErrorDetail = {
+  kind: ErrorKind;
+  code: number;
+  errno: number | null;
+  operation: string;
+  path: string | null;
+  message: string;
+};
+
+export function kindOfCode(code: number): ErrorKind {
+  for (const [kind, c] of Object.entries(ErrorCode)) {
+    if (c === code) return kind as ErrorKind;
+  }
+  return "Io";
+}
+
 export type Lib = Deno.DynamicLibrary<typeof symbols>;
 let lib: Lib | null = null;
 
@@ -69,6 +201,36 @@ export function getLastError(): string {
   return new TextDecoder().decode(buf).replace(/\0+$/, "");
 }
 
+// Parse and free a JSON C string returned by the library.
+export function takeJson<T>(ptr: Deno.PointerValue): T | null {
+  if (!lib || !ptr) return null;
+  try {
+    return JSON.parse(new Deno.UnsafePointerView(ptr).getCString()) as T;
+  } catch {
+    return null;
+  } finally {
+    lib.symbols.serial_free_cstr(ptr);
+  }
+}
+
+/** Last error raised on the calling thread (synchronous calls). */
+export function getLastErrorDetail(): ErrorDetail | null {
+  if (!lib) return null;
+  return takeJson(lib.symbols.serial_err_json());
+}
+
+/**
+ * Last error of a nonblocking read (1), write (2), wait_event (4) or control
+ * call such as send_break (8) on `h`.
+ */
+export function getIoErrorDetail(
+  h: Handle,
+  which: 1 | 2 | 4 | 8,
+): ErrorDetail | null {
+  if (!lib) return null;
+  return takeJson(lib.symbols.serial_io_err_json(h, which));
+}
+
 export function requireLib(): Lib {
   if (!lib) throw new Error("FFI library is not loaded");
   return lib;
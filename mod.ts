@@ -1,20 +1,130 @@
 // Public API for JSR
 import { ensureLibrary } from "./loader.ts";
-import { getLastError, type Handle, load, requireLib } from "./ffi.ts";
+import {
+  dataCallback,
+  ErrorCode,
+  type ErrorDetail,
+  type ErrorKind,
+  getIoErrorDetail,
+  getLastErrorDetail,
+  type Handle,
+  kindOfCode,
+  load,
+  requireLib,
+  takeJson,
+} from "./ffi.ts";
+
+export type { ErrorKind };
+
+/** Error thrown by SerialPort operations, with the native error details. */
+export class SerialPortError extends Error {
+  readonly kind: ErrorKind;
+  readonly code: number;
+  readonly errno: number | null;
+  readonly operation: string;
+  readonly path: string | null;
+
+  constructor(code: number, operation: string, detail: ErrorDetail | null) {
+    // Ignore details left over from an earlier failure.
+    const d = detail?.code === code ? detail : null;
+    super(d?.message || `${operation} failed: ${code}`);
+    this.name = "SerialPortError";
+    this.kind = d?.kind ?? kindOfCode(code);
+    this.code = code;
+    this.errno = d?.errno ?? null;
+    this.operation = d?.operation ?? operation;
+    this.path = d?.path ?? null;
+  }
+}
 
 export type OpenOptions = {
   path: string;
   baudRate: number;
   dataBits?: 5 | 6 | 7 | 8;
-  parity?: "none" | "odd" | "even";
-  stopBits?: 1 | 2;
+  parity?: PortConfig["parity"];
+  stopBits?: PortConfig["stopBits"];
   rtscts?: boolean;
+  // IXON/IXOFF/IXANY individually on Linux; elsewhere xon or xoff enables
+  // software flow control and xany is ignored.
   xon?: boolean;
   xoff?: boolean;
   xany?: boolean;
-  readTimeoutMs?: number; // -1: infinite (internally large timeout)
+  xonChar?: number; // Linux only
+  xoffChar?: number; // Linux only
+  readTimeoutMs?: number; // -1: infinite (blocks until data or close)
+  // DTR/RTS levels set before open() resolves; omitted leaves them as the
+  // driver left them (Linux asserts both on open). With `hupcl: false` on a
+  // previous close, DTR stays high and boards wired to reset on a DTR edge
+  // keep running.
+  dtr?: boolean;
+  rts?: boolean;
+  hupcl?: boolean; // Linux only; see PortConfig
+  clocal?: boolean; // Linux only; see PortConfig
+  lowLatency?: boolean; // Linux only; see PortConfig
+  readMinBytes?: number; // see PortConfig
+  interByteTimeoutUs?: number; // see PortConfig
+  // Refuse to share the device with other processes (TIOCEXCL + flock on
+  // Unix) or other handles in this one; conflicts fail with kind "Busy",
+  // including opening a port another process holds exclusively. Otherwise
+  // the port can be opened again (always exclusive on Windows).
+  exclusive?: boolean;
+  // Take a UUCP lock file (LCK..<device>, as minicom/picocom use) in
+  // `lockDir` (default /var/lock) while open. Paths in a subdirectory of
+  // /dev keep it, with `/` as `_`: /dev/pts/3 locks LCK..pts_3. Unix only.
+  lockFile?: boolean;
+  lockDir?: string;
+  // Put the tty's previous termios settings and DTR/RTS back on close (or at
+  // exit if the port is still open), for getty and friends. Linux only.
+  restoreOnClose?: boolean;
+  // Bytes buffered by the background reader thread; reads then no longer
+  // occupy an FFI worker thread. Linux only.
+  rxBufferSize?: number;
+};
+
+// Bytes `readable` lets queue up unread before it pauses delivery.
+const READABLE_HIGH_WATER = 64 * 1024;
+
+/** Line settings of an open port. */
+export type PortConfig = {
+  baudRate: number; // any integer rate on Linux (termios2 BOTHER)
+  dataBits: 5 | 6 | 7 | 8;
+  // mark/space and 1.5 stop bits (with 5 data bits): Linux only. 2 stop bits
+  // need 6 or more data bits; moving dataBits to or from 5 on a port with
+  // 1.5 or 2 stop bits must also give stopBits.
+  parity: "none" | "odd" | "even" | "mark" | "space";
+  stopBits: 1 | 1.5 | 2;
+  flowControl: "none" | "hardware" | "software";
+  // Software flow control, bit by bit (Linux only; getConfig omits them
+  // elsewhere). setConfig applies them after flowControl, whose "software"
+  // sets both xon and xoff.
+  xon?: boolean; // IXON: pause output while XOFF is in effect
+  xoff?: boolean; // IXOFF: send XOFF/XON as the input buffer fills/drains
+  xany?: boolean; // IXANY: any received byte restarts output
+  xonChar?: number; // VSTART, default 0x11
+  xoffChar?: number; // VSTOP, default 0x13
+  // Linux only, like the flow control bits above.
+  hupcl?: boolean; // drop DTR/RTS when the port is closed
+  clocal?: boolean; // ignore DCD instead of waiting for carrier
+  // ASYNC_LOW_LATENCY: USB adapters hand over data every 1 ms instead of
+  // every 16 ms. Linux only; getConfig omits it where the driver (e.g. a
+  // pty) has no such setting, and setting it there fails with kind
+  // "Unsupported" before anything else is changed.
+  lowLatency?: boolean;
+  // When a read completes (not with rxBufferSize): once readMinBytes
+  // (default 1) have arrived, or, with interByteTimeoutUs > 0, once the line
+  // has then been quiet that long. A full buffer or the read timeout ends it
+  // early.
+  readMinBytes?: number;
+  interByteTimeoutUs?: number;
 };
 
+const TCFLOW_ACTIONS = {
+  suspendOutput: 0,
+  resumeOutput: 1,
+  sendXoff: 2,
+  sendXon: 3,
+} as const;
+
 let libLoaded = false;
 async function init(): Promise<void> {
   if (libLoaded) return;
@@ -23,6 +133,47 @@ async function init(): Promise<void> {
   libLoaded = true;
 }
 
+/** Events reported by {@linkcode SerialPort.prototype.waitEvent}. */
+export type SerialEvent =
+  | "cts"
+  | "dsr"
+  | "dcd"
+  | "ri"
+  | "break"
+  | "frame"
+  | "parity"
+  | "overrun";
+
+const eventBits: Record<SerialEvent, number> = {
+  cts: 1 << 0,
+  dsr: 1 << 1,
+  dcd: 1 << 2,
+  ri: 1 << 3,
+  break: 1 << 4,
+  frame: 1 << 5,
+  parity: 1 << 6,
+  overrun: 1 << 7,
+};
+
+function eventsOf(mask: number): SerialEvent[] {
+  return (Object.keys(eventBits) as SerialEvent[]).filter((e) =>
+    mask & eventBits[e]
+  );
+}
+
+/** Handlers for {@linkcode SerialPort.prototype.subscribe}. */
+export type SerialSubscriber = {
+  /** A received chunk, as soon as the kernel delivers it. */
+  data?: (chunk: Uint8Array) => void;
+  /** Modem-line transitions / line errors that fired. */
+  event?: (events: SerialEvent[]) => void;
+  /**
+   * Last call, after unsubscribing or closing (no argument) or when the
+   * stream ended on its own (e.g. kind "Disconnected").
+   */
+  close?: (error?: SerialPortError) => void;
+};
+
 export type PortInfo = {
   path: string;
   manufacturer?: string;
@@ -35,6 +186,7 @@ export type PortInfo = {
 export class SerialPort implements AsyncDisposable {
   #h: Handle;
   #closed = false;
+  #subscription: Deno.UnsafeCallback<typeof dataCallback> | null = null;
 
   private constructor(h: Handle) {
     this.#h = h;
@@ -72,61 +224,161 @@ export class SerialPort implements AsyncDisposable {
   static async open(opts: OpenOptions): Promise<SerialPort> {
     await init();
     const lib = requireLib();
-    const enc = new TextEncoder();
-    const cpath = enc.encode(opts.path + "\0");
-    const parity = { none: 0, odd: 1, even: 2 }[opts.parity ?? "none"];
-    const rc = lib.symbols.serial_open(
-      Deno.UnsafePointer.of(cpath),
-      opts.baudRate >>> 0,
-      opts.dataBits ?? 8,
-      parity,
-      opts.stopBits ?? 1,
-      opts.rtscts ? 1 : 0,
-      opts.xon ? 1 : 0,
-      opts.xoff ? 1 : 0,
-      opts.xany ? 1 : 0,
-      opts.readTimeoutMs ?? -1,
-    );
-    if (rc !== 0) throw new Error(getLastError() || `open failed: ${rc}`);
-    const h = lib.symbols.serial_last_handle(); // bigint
-    return new SerialPort(h as unknown as bigint);
+    const linux = Deno.build.os === "linux";
+    const native = {
+      version: 1,
+      path: opts.path,
+      baudRate: opts.baudRate,
+      dataBits: opts.dataBits,
+      parity: opts.parity,
+      stopBits: opts.stopBits,
+      flowControl: opts.rtscts
+        ? "hardware"
+        : linux
+        ? undefined
+        : opts.xon || opts.xoff
+        ? "software"
+        : undefined,
+      xon: linux ? opts.xon : undefined,
+      xoff: linux ? opts.xoff : undefined,
+      xany: linux ? opts.xany : undefined,
+      xonChar: opts.xonChar,
+      xoffChar: opts.xoffChar,
+      hupcl: opts.hupcl,
+      clocal: opts.clocal,
+      lowLatency: opts.lowLatency,
+      readMinBytes: opts.readMinBytes,
+      interByteTimeoutUs: opts.interByteTimeoutUs,
+      dtr: opts.dtr,
+      rts: opts.rts,
+      readTimeoutMs: opts.readTimeoutMs,
+      exclusive: opts.exclusive,
+      lockFile: opts.lockFile,
+      lockDir: opts.lockDir,
+      restoreOnClose: opts.restoreOnClose,
+    };
+    const json = new TextEncoder().encode(JSON.stringify(native) + "\0");
+    const hBuf = new BigUint64Array(1);
+    const rc = lib.symbols.serial_open_ex(
+      json as unknown as BufferSource,
+      hBuf as unknown as BufferSource,
+    );
+    if (rc !== 0) throw new SerialPortError(rc, "open", getLastErrorDetail());
+    const port = new SerialPort(hBuf[0]);
+    if (opts.rxBufferSize) {
+      try {
+        port.setRxBuffer(opts.rxBufferSize);
+      } catch (e) {
+        port.close();
+        throw e;
+      }
+    }
+    return port;
   }
 
+  /**
+   * Received data as a stream. On Linux it is pushed by the library's
+   * background thread (see `subscribe`) from the first read on, starting with
+   * anything left in the receive buffer; while more than 64 KiB is queued
+   * unread, delivery pauses and the kernel (or the receive buffer) holds the
+   * rest. Elsewhere it uses blocking reads. While the stream is being read,
+   * other reads fail with kind "Busy"; do not combine with `subscribe`.
+   */
   get readable(): ReadableStream<Uint8Array> {
+    // Ends the current subscription without ending the stream; null while
+    // not subscribed.
+    let pause: (() => void) | null = null;
+    // Cleared once `subscribe` turns out to be unavailable here.
+    let push = true;
+    const resume = (controller: ReadableByteStreamController) => {
+      let paused = false;
+      const unsubscribe = this.subscribe({
+        data: (chunk) => {
+          try {
+            controller.enqueue(chunk);
+          } catch { /* stream already finished */ }
+          if (
+            !paused && (controller.desiredSize ?? 0) < -READABLE_HIGH_WATER
+          ) {
+            pause?.();
+          }
+        },
+        close: (error) => {
+          if (paused) return;
+          pause = null;
+          try {
+            if (error && error.kind !== "Disconnected") {
+              controller.error(error);
+            } else {
+              controller.close();
+            }
+          } catch { /* stream already finished */ }
+        },
+      });
+      pause = () => {
+        paused = true;
+        pause = null;
+        unsubscribe();
+      };
+    };
     return new ReadableStream<Uint8Array>({
       type: "bytes",
+      // Subscribing waits for the first read, so merely getting the stream
+      // leaves the other read methods usable.
       pull: async (controller) => {
         if (this.#closed) {
           controller.close();
           return;
         }
+        if (push) {
+          // Already subscribed: data arrives through the callback.
+          if (pause) return;
+          try {
+            resume(controller);
+            return;
+          } catch (e) {
+            // Not available on this platform: fall back to blocking reads.
+            if (!(e instanceof SerialPortError && e.kind === "Unsupported")) {
+              throw e;
+            }
+            push = false;
+          }
+        }
         const buf = new Uint8Array(8192);
-        const nBig = await requireLib().symbols.serial_read(
-          this.#h as unknown as bigint,
-          buf as unknown as BufferSource,
-          BigInt(buf.length),
-          -1,
-        );
-        if (nBig < 0n) {
-          // If closing in progress, treat as stream close
+        while (true) {
           if (this.#closed) {
             controller.close();
             return;
           }
+          const nBig = await requireLib().symbols.serial_read(
+            this.#h as unknown as bigint,
+            buf as unknown as BufferSource,
+            BigInt(buf.length),
+            -1,
+          );
+          // Timed out without data: the device is just idle, keep waiting.
+          if (nBig === 0n) continue;
+          if (nBig > 0n) {
+            controller.enqueue(buf.subarray(0, Number(nBig)));
+            return;
+          }
+          const code = Number(nBig);
+          // End of stream, cancelled, or closing in progress: end the stream
+          if (
+            this.#closed || code === ErrorCode.Disconnected ||
+            code === ErrorCode.Cancelled
+          ) {
+            controller.close();
+            return;
+          }
           controller.error(
-            new Error(getLastError() || `read failed: ${nBig.toString()}`),
+            new SerialPortError(code, "read", getIoErrorDetail(this.#h, 1)),
           );
           return;
         }
-        if (nBig === 0n) {
-          // Timeout (0) or EOF: close stream
-          controller.close();
-          return;
-        }
-        controller.enqueue(buf.subarray(0, Number(nBig)));
       },
       cancel: async () => {
-        // Delegate explicit cancel to close()
+        // Delegate explicit cancel to close(), which wakes the pending read
         if (!this.#closed) {
           await this.close();
         }
@@ -144,12 +396,183 @@ export class SerialPort implements AsyncDisposable {
         BigInt(slice.length),
       );
       if (nBig < 0n) {
-        throw new Error(getLastError() || `write failed: ${nBig.toString()}`);
+        throw new SerialPortError(
+          Number(nBig),
+          "write",
+          getIoErrorDetail(this.#h, 2),
+        );
       }
       off += Number(nBig);
     }
   }
 
+  /**
+   * Read exactly `length` bytes. `timeoutMs` is one deadline for the whole
+   * call; if it passes, this throws (kind "TimedOut") and the bytes read so
+   * far are returned by the next read. Do not mix with `readable`.
+   */
+  async readExact(
+    length: number,
+    opts: { timeoutMs?: number } = {},
+  ): Promise<Uint8Array> {
+    const buf = new Uint8Array(length);
+    const nBig = await requireLib().symbols.serial_read_exact(
+      this.#h as unknown as bigint,
+      buf as unknown as BufferSource,
+      BigInt(length),
+      opts.timeoutMs ?? -1,
+    );
+    if (nBig < 0n) {
+      throw new SerialPortError(
+        Number(nBig),
+        "read_exact",
+        getIoErrorDetail(this.#h, 1),
+      );
+    }
+    return buf;
+  }
+
+  /**
+   * Read up to and including `delimiter` (e.g. "\r\n"); bytes after it are
+   * kept for the next read. If `maxLength` bytes arrive first, they are
+   * returned without the delimiter. Deadline as in `readExact`.
+   */
+  async readUntil(
+    delimiter: Uint8Array | string,
+    opts: { timeoutMs?: number; maxLength?: number } = {},
+  ): Promise<Uint8Array> {
+    const delim = typeof delimiter === "string"
+      ? new TextEncoder().encode(delimiter)
+      : delimiter;
+    const buf = new Uint8Array(opts.maxLength ?? 8192);
+    const nBig = await requireLib().symbols.serial_read_until(
+      this.#h as unknown as bigint,
+      delim as unknown as BufferSource,
+      BigInt(delim.length),
+      buf as unknown as BufferSource,
+      BigInt(buf.length),
+      opts.timeoutMs ?? -1,
+    );
+    if (nBig < 0n) {
+      throw new SerialPortError(
+        Number(nBig),
+        "read_until",
+        getIoErrorDetail(this.#h, 1),
+      );
+    }
+    return buf.subarray(0, Number(nBig));
+  }
+
+  /**
+   * Read one frame: the bytes up to a silence of `gapUs` microseconds or
+   * `gapChars` character times at the current settings, whichever is
+   * longer. `timeoutMs` bounds the wait for the first byte; null if none
+   * came. `firstByteNs` is a monotonic timestamp (CLOCK_MONOTONIC on Unix),
+   * or 0 if the frame starts with already peeked, unread or left-over bytes,
+   * whose arrival time is not kept (as in `readTimestamped`).
+   * Do not mix with {@linkcode SerialPort.prototype.readable}.
+   */
+  async readFrame(
+    opts: {
+      gapUs?: number;
+      gapChars?: number;
+      timeoutMs?: number;
+      maxLength?: number;
+    },
+  ): Promise<{ data: Uint8Array; firstByteNs: bigint } | null> {
+    const buf = new Uint8Array(opts.maxLength ?? 8192);
+    const tsBuf = new BigUint64Array(1);
+    const nBig = await requireLib().symbols.serial_read_frame(
+      this.#h as unknown as bigint,
+      buf as unknown as BufferSource,
+      BigInt(buf.length),
+      opts.gapUs ?? 0,
+      opts.gapChars ?? 0,
+      opts.timeoutMs ?? -1,
+      tsBuf as unknown as BufferSource,
+    );
+    if (nBig < 0n) {
+      throw new SerialPortError(
+        Number(nBig),
+        "read_frame",
+        getIoErrorDetail(this.#h, 1),
+      );
+    }
+    if (nBig === 0n) return null;
+    return { data: buf.subarray(0, Number(nBig)), firstByteNs: tsBuf[0] };
+  }
+
+  /**
+   * Read one chunk with the monotonic time it arrived (`arrivalNs`, taken
+   * right after the kernel read; 0 for already peeked, unread or left-over
+   * bytes, whose arrival time is not kept). With `split`, the chunk is what
+   * a single read returned, so the time is accurate to its first byte. Null
+   * if `timeoutMs` (for this call only; default: the port's read timeout)
+   * passed without data. Do not mix with `readable`.
+   */
+  async readTimestamped(
+    opts: { timeoutMs?: number; maxLength?: number; split?: boolean } = {},
+  ): Promise<{ data: Uint8Array; arrivalNs: bigint } | null> {
+    const buf = new Uint8Array(opts.maxLength ?? 8192);
+    const tsBuf = new BigUint64Array(1);
+    const nBig = await requireLib().symbols.serial_read_ts(
+      this.#h as unknown as bigint,
+      buf as unknown as BufferSource,
+      BigInt(buf.length),
+      opts.timeoutMs ?? -1,
+      opts.split ? 1 : 0,
+      tsBuf as unknown as BufferSource,
+    );
+    if (nBig < 0n) {
+      throw new SerialPortError(
+        Number(nBig),
+        "read_ts",
+        getIoErrorDetail(this.#h, 1),
+      );
+    }
+    if (nBig === 0n) return null;
+    return { data: buf.subarray(0, Number(nBig)), arrivalNs: tsBuf[0] };
+  }
+
+  /**
+   * Up to `length` of the next bytes, without consuming them; only what has
+   * already arrived, so possibly fewer (or none). Do not mix with `readable`.
+   */
+  peek(length: number): Uint8Array {
+    const buf = new Uint8Array(length);
+    const nBig = requireLib().symbols.serial_peek(
+      this.#h as unknown as bigint,
+      buf as unknown as BufferSource,
+      BigInt(length),
+    );
+    if (nBig < 0n) {
+      throw new SerialPortError(Number(nBig), "peek", getLastErrorDetail());
+    }
+    return buf.subarray(0, Number(nBig));
+  }
+
+  /** Drop up to `length` peeked bytes; returns how many were dropped. */
+  consume(length: number): number {
+    const nBig = requireLib().symbols.serial_consume(
+      this.#h as unknown as bigint,
+      BigInt(length),
+    );
+    if (nBig < 0n) {
+      throw new SerialPortError(Number(nBig), "consume", getLastErrorDetail());
+    }
+    return Number(nBig);
+  }
+
+  /** Push `data` back so the next read of any kind returns it first. */
+  unread(data: Uint8Array): void {
+    const rc = requireLib().symbols.serial_unread(
+      this.#h as unknown as bigint,
+      data as unknown as BufferSource,
+      BigInt(data.length),
+    );
+    if (rc !== 0) throw new SerialPortError(rc, "unread", getLastErrorDetail());
+  }
+
   flush(opts: { in?: boolean; out?: boolean } = {}): void {
     const fi = opts.in ? 1 : 0;
     const fo = opts.out ? 1 : 0;
@@ -158,12 +581,71 @@ export class SerialPort implements AsyncDisposable {
       fi,
       fo,
     );
-    if (rc !== 0) throw new Error(getLastError() || `flush failed: ${rc}`);
+    if (rc !== 0) throw new SerialPortError(rc, "flush", getLastErrorDetail());
   }
 
   drain(): void {
     const rc = requireLib().symbols.serial_drain(this.#h as unknown as bigint);
-    if (rc !== 0) throw new Error(getLastError() || `drain failed: ${rc}`);
+    if (rc !== 0) throw new SerialPortError(rc, "drain", getLastErrorDetail());
+  }
+
+  /**
+   * Change any subset of the line settings without reopening the port (DTR
+   * and buffered data are left alone). Invalid values change nothing.
+   */
+  setConfig(config: Partial<PortConfig>): void {
+    const json = new TextEncoder().encode(JSON.stringify(config) + "\0");
+    const rc = requireLib().symbols.serial_set_config(
+      this.#h as unknown as bigint,
+      json as unknown as BufferSource,
+    );
+    if (rc !== 0) {
+      throw new SerialPortError(rc, "set_config", getLastErrorDetail());
+    }
+  }
+
+  /**
+   * The line settings in effect, as reported by the device. `baudRate` is the
+   * requested rate; `effectiveBaudRate` is what the UART actually runs at.
+   */
+  getConfig(): PortConfig & { effectiveBaudRate: number } {
+    const config = takeJson<PortConfig & { effectiveBaudRate: number }>(
+      requireLib().symbols.serial_get_config(this.#h as unknown as bigint),
+    );
+    if (!config) {
+      const detail = getLastErrorDetail();
+      throw new SerialPortError(detail?.code ?? -1, "get_config", detail);
+    }
+    return config;
+  }
+
+  /**
+   * After queued output has been sent, hold break for `breakUs` microseconds,
+   * then mark for `markAfterBreakUs`, timed natively (DMX512, LIN,
+   * bootloaders). Resolves once the mark-after-break has elapsed. Each
+   * duration is limited to 10 s.
+   */
+  async sendBreak(breakUs: number, markAfterBreakUs = 0): Promise<void> {
+    const rc = await requireLib().symbols.serial_send_break(
+      this.#h as unknown as bigint,
+      breakUs,
+      markAfterBreakUs,
+    );
+    if (rc !== 0) {
+      throw new SerialPortError(rc, "send_break", getIoErrorDetail(this.#h, 8));
+    }
+  }
+
+  /**
+   * Send XON/XOFF right away, ahead of queued output, or suspend/resume our
+   * own output (tcflow). Unix only.
+   */
+  tcflow(action: keyof typeof TCFLOW_ACTIONS): void {
+    const rc = requireLib().symbols.serial_tcflow(
+      this.#h as unknown as bigint,
+      TCFLOW_ACTIONS[action],
+    );
+    if (rc !== 0) throw new SerialPortError(rc, "tcflow", getLastErrorDetail());
   }
 
   set(opts: { rts?: boolean; dtr?: boolean; brk?: boolean }): void {
@@ -176,7 +658,7 @@ export class SerialPort implements AsyncDisposable {
       dtr,
       brk,
     );
-    if (rc !== 0) throw new Error(getLastError() || `set failed: ${rc}`);
+    if (rc !== 0) throw new SerialPortError(rc, "set", getLastErrorDetail());
   }
 
   get(): { cts: boolean; dsr: boolean; dcd: boolean; ri: boolean } {
@@ -185,7 +667,7 @@ export class SerialPort implements AsyncDisposable {
       this.#h as unknown as bigint,
       maskBuf as unknown as BufferSource,
     );
-    if (rc !== 0) throw new Error(getLastError() || `get failed: ${rc}`);
+    if (rc !== 0) throw new SerialPortError(rc, "get", getLastErrorDetail());
     const m = maskBuf[0] >>> 0;
     return {
       cts: !!(m & (1 << 0)),
@@ -195,11 +677,140 @@ export class SerialPort implements AsyncDisposable {
     };
   }
 
+  /**
+   * Serve reads from a receive buffer of `capacity` bytes filled by a shared
+   * background thread (0 turns it off; at most 64 MiB). When full, the
+   * oldest bytes are dropped and counted in
+   * {@linkcode SerialPort.prototype.rxStats}.
+   */
+  setRxBuffer(capacity: number): void {
+    const rc = requireLib().symbols.serial_set_rx_buffer(
+      this.#h as unknown as bigint,
+      BigInt(capacity),
+    );
+    if (rc !== 0) {
+      throw new SerialPortError(rc, "set_rx_buffer", getLastErrorDetail());
+    }
+  }
+
+  /** Bytes waiting in the receive buffer and bytes dropped on overflow. */
+  rxStats(): { buffered: number; overflow: number } {
+    const buffered = new BigUint64Array(1);
+    const overflow = new BigUint64Array(1);
+    const rc = requireLib().symbols.serial_rx_stats(
+      this.#h as unknown as bigint,
+      buffered as unknown as BufferSource,
+      overflow as unknown as BufferSource,
+    );
+    if (rc !== 0) {
+      throw new SerialPortError(rc, "rx_stats", getLastErrorDetail());
+    }
+    return { buffered: Number(buffered[0]), overflow: Number(overflow[0]) };
+  }
+
+  /**
+   * Wait until one of `events` fires and return the ones that did (empty on
+   * timeout). Where the driver keeps transition counters (TIOCGICOUNT, most
+   * Linux serial drivers), transitions are latched, so short pulses are not
+   * missed; elsewhere the lines are sampled and a pulse shorter than a few
+   * milliseconds can be. Line-error events need those counters too.
+   * Requesting an event the port cannot report throws kind "Unsupported".
+   */
+  async waitEvent(
+    events: SerialEvent[],
+    opts: { timeoutMs?: number } = {},
+  ): Promise<SerialEvent[]> {
+    const mask = events.reduce((m, e) => m | eventBits[e], 0);
+    const out = new Uint32Array(1);
+    const rc = await requireLib().symbols.serial_wait_event(
+      this.#h as unknown as bigint,
+      mask,
+      opts.timeoutMs ?? -1,
+      out as unknown as BufferSource,
+    );
+    if (rc !== 0) {
+      throw new SerialPortError(rc, "wait_event", getIoErrorDetail(this.#h, 4));
+    }
+    return eventsOf(out[0]);
+  }
+
+  /**
+   * Receive data and events as they arrive, from the library's background
+   * thread (Linux only). There is no backpressure; a later call replaces the
+   * current subscriber. While subscribed, reads that would go to the device
+   * fail with kind "Busy". Returns a function that unsubscribes.
+   */
+  subscribe(handlers: SerialSubscriber): () => void {
+    const lib = requireLib();
+    const cb = Deno.UnsafeCallback.threadSafe(
+      dataCallback,
+      (_userData, kind, value, data, len) => {
+        if (kind === 0) {
+          if (!data) return;
+          const view = Deno.UnsafePointerView.getArrayBuffer(data, Number(len));
+          // The buffer is only valid during this call.
+          handlers.data?.(new Uint8Array(view).slice());
+          return;
+        }
+        if (kind === 1) {
+          handlers.event?.(eventsOf(value));
+          return;
+        }
+        // Final call: the library no longer references the callback.
+        if (this.#subscription === cb) this.#subscription = null;
+        setTimeout(() => cb.close(), 0);
+        handlers.close?.(
+          value === 0
+            ? undefined
+            : new SerialPortError(value, "read", getIoErrorDetail(this.#h, 1)),
+        );
+      },
+    );
+    const rc = lib.symbols.serial_set_data_callback(
+      this.#h as unknown as bigint,
+      cb.pointer,
+      null,
+    );
+    if (rc !== 0) {
+      cb.close();
+      throw new SerialPortError(rc, "subscribe", getLastErrorDetail());
+    }
+    this.#subscription = cb;
+    return () => {
+      if (this.#closed || this.#subscription !== cb) return;
+      // Does not wait: `close` runs once the background thread lets go.
+      lib.symbols.serial_set_data_callback(
+        this.#h as unknown as bigint,
+        null,
+        null,
+      );
+    };
+  }
+
+  /**
+   * Interrupt a pending read, write and/or waitEvent (all by default). The
+   * interrupted call ends with kind "Cancelled" (a read keeps what it had
+   * received for the next read); calls made afterwards are not affected.
+   */
+  cancel(
+    opts: { read?: boolean; write?: boolean; event?: boolean } = {},
+  ): void {
+    const all = opts.read == null && opts.write == null && opts.event == null;
+    const which = (all || opts.read ? 1 : 0) | (all || opts.write ? 2 : 0) |
+      (all || opts.event ? 4 : 0);
+    if (which === 0) return;
+    const rc = requireLib().symbols.serial_cancel(
+      this.#h as unknown as bigint,
+      which,
+    );
+    if (rc !== 0) throw new SerialPortError(rc, "cancel", getLastErrorDetail());
+  }
+
   close(): void {
     if (this.#closed) return;
     const rc = requireLib().symbols.serial_close(this.#h as unknown as bigint);
     this.#closed = true;
-    if (rc !== 0) throw new Error(getLastError() || `close failed: ${rc}`);
+    if (rc !== 0) throw new SerialPortError(rc, "close", getLastErrorDetail());
   }
 
   [Symbol.asyncDispose](): Promise<void> {
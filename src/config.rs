@@ -0,0 +1,822 @@
+//! JSON port settings for `serial_open_ex`, `serial_set_config` and
+//! `serial_get_config`
+use crate::{
+    error::{ErrorKind, SerialError},
+    Port,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serialport::SerialPort;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    // Parity bit stuck at 1 / 0 (CMSPAR), e.g. a 9th address bit on
+    // multidrop buses. Linux only.
+    Mark,
+    Space,
+}
+
+/// 1, 1.5 or 2 in JSON. 1.5 needs 5 data bits (CSTOPB with CS5) and Linux;
+/// 2 needs at least 6.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    OnePointFive,
+    Two,
+}
+
+impl Serialize for StopBits {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            StopBits::One => s.serialize_u8(1),
+            StopBits::OnePointFive => s.serialize_f64(1.5),
+            StopBits::Two => s.serialize_u8(2),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StopBits {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let n = f64::deserialize(d)?;
+        if n == 1.0 {
+            Ok(StopBits::One)
+        } else if n == 1.5 {
+            Ok(StopBits::OnePointFive)
+        } else if n == 2.0 {
+            Ok(StopBits::Two)
+        } else {
+            Err(de::Error::custom(format!("unsupported stopBits {n}")))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    None,
+    Hardware,
+    Software,
+}
+
+/// Settings in effect on a port, as read back from the device.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    // As requested; the UART may only approximate it.
+    baud_rate: u32,
+    // As reported by the driver.
+    effective_baud_rate: u32,
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: StopBits,
+    flow_control: FlowControl,
+    // Linux only; absent elsewhere.
+    #[serde(flatten)]
+    software_flow: Option<SoftwareFlow>,
+    #[serde(flatten)]
+    modem_control: Option<ModemControl>,
+    // Linux, and only where the driver has serial_struct (not ptys).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    low_latency: Option<bool>,
+    read_min_bytes: usize,
+    inter_byte_timeout_us: u32,
+}
+
+/// When a direct `serial_read` completes; reads from the receive buffer
+/// (serial_set_rx_buffer) return whatever is buffered.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadPolicy {
+    // Keep reading until at least this many bytes are in (or the buffer is
+    // full, or the read timeout expires).
+    pub min_bytes: usize,
+    // Then keep reading until the line has been quiet this long.
+    pub inter_byte_timeout: Option<Duration>,
+}
+
+impl Default for ReadPolicy {
+    // Return as soon as anything arrives.
+    fn default() -> Self {
+        ReadPolicy {
+            min_bytes: 1,
+            inter_byte_timeout: None,
+        }
+    }
+}
+
+/// The individual software flow control bits and their characters.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SoftwareFlow {
+    // IXON: pause output when XOFF is received.
+    xon: bool,
+    // IXOFF: send XOFF/XON as the input buffer fills and drains.
+    xoff: bool,
+    // IXANY: any received byte, not just XON, restarts output.
+    xany: bool,
+    xon_char: u8,
+    xoff_char: u8,
+}
+
+/// How the port treats the modem lines (Linux).
+#[derive(Serialize)]
+pub struct ModemControl {
+    // Drop DTR/RTS on the last close.
+    hupcl: bool,
+    // Ignore DCD: opens and reads never wait for carrier.
+    clocal: bool,
+}
+
+impl Config {
+    /// Time one character takes on the wire: start bit, data bits, parity
+    /// bit and stop bits at the effective rate.
+    pub fn char_time(&self) -> Duration {
+        let parity = u64::from(self.parity != Parity::None);
+        let stop_halves = match self.stop_bits {
+            StopBits::One => 2,
+            StopBits::OnePointFive => 3,
+            StopBits::Two => 4,
+        };
+        let half_bits = 2 * (1 + u64::from(self.data_bits) + parity) + stop_halves;
+        Duration::from_nanos(half_bits * 500_000_000 / u64::from(self.effective_baud_rate.max(1)))
+    }
+
+    pub fn read(port: &Port, requested_baud: u32, policy: ReadPolicy) -> Result<Self, SerialError> {
+        let data_bits = match port.data_bits()? {
+            serialport::DataBits::Five => 5,
+            serialport::DataBits::Six => 6,
+            serialport::DataBits::Seven => 7,
+            serialport::DataBits::Eight => 8,
+        };
+        #[cfg(target_os = "linux")]
+        let (parity, stop_bits) = linux::read(port, data_bits)?;
+        #[cfg(not(target_os = "linux"))]
+        let (parity, stop_bits) = (
+            match port.parity()? {
+                serialport::Parity::None => Parity::None,
+                serialport::Parity::Odd => Parity::Odd,
+                serialport::Parity::Even => Parity::Even,
+            },
+            match port.stop_bits()? {
+                serialport::StopBits::One => StopBits::One,
+                serialport::StopBits::Two => StopBits::Two,
+            },
+        );
+        #[cfg(target_os = "linux")]
+        let effective_baud_rate = linux::baud_rate(port)?;
+        #[cfg(not(target_os = "linux"))]
+        let effective_baud_rate = port.baud_rate()?;
+        #[cfg(target_os = "linux")]
+        let (software_flow, modem_control) = (
+            Some(linux::read_software_flow(port)?),
+            Some(linux::read_modem_control(port)?),
+        );
+        #[cfg(not(target_os = "linux"))]
+        let (software_flow, modem_control) = (None, None);
+        #[cfg(target_os = "linux")]
+        let low_latency = linux::low_latency(port);
+        #[cfg(not(target_os = "linux"))]
+        let low_latency = None;
+        Ok(Config {
+            baud_rate: requested_baud,
+            effective_baud_rate,
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control: match port.flow_control()? {
+                serialport::FlowControl::None => FlowControl::None,
+                serialport::FlowControl::Hardware => FlowControl::Hardware,
+                serialport::FlowControl::Software => FlowControl::Software,
+            },
+            software_flow,
+            modem_control,
+            low_latency,
+            read_min_bytes: policy.min_bytes,
+            inter_byte_timeout_us: policy
+                .inter_byte_timeout
+                .map_or(0, |d| d.as_micros() as u32),
+        })
+    }
+}
+
+/// A partial update; absent fields are left unchanged.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ConfigUpdate {
+    baud_rate: Option<u32>,
+    data_bits: Option<u8>,
+    parity: Option<Parity>,
+    stop_bits: Option<StopBits>,
+    flow_control: Option<FlowControl>,
+    // Applied after `flow_control`, so they refine "software" (which sets
+    // both xon and xoff). Linux only.
+    xon: Option<bool>,
+    xoff: Option<bool>,
+    xany: Option<bool>,
+    xon_char: Option<u8>,
+    xoff_char: Option<u8>,
+    // Linux only.
+    hupcl: Option<bool>,
+    clocal: Option<bool>,
+    // ASYNC_LOW_LATENCY via TIOCSSERIAL. Linux only.
+    low_latency: Option<bool>,
+    // The read policy; kept by the handle rather than the device. 0 turns
+    // the inter-byte timeout off.
+    read_min_bytes: Option<usize>,
+    inter_byte_timeout_us: Option<u32>,
+}
+
+fn invalid(what: String) -> SerialError {
+    SerialError::new(ErrorKind::InvalidConfig, what)
+}
+
+fn parse_json<'a, T: Deserialize<'a>>(json: &'a str, what: &str) -> Result<T, SerialError> {
+    serde_json::from_str(json).map_err(|e| invalid(format!("invalid {what}: {e}")))
+}
+
+fn data_bits(n: u8) -> Result<serialport::DataBits, SerialError> {
+    match n {
+        5 => Ok(serialport::DataBits::Five),
+        6 => Ok(serialport::DataBits::Six),
+        7 => Ok(serialport::DataBits::Seven),
+        8 => Ok(serialport::DataBits::Eight),
+        _ => Err(invalid(format!("unsupported dataBits {n}"))),
+    }
+}
+
+impl ConfigUpdate {
+    pub fn parse(json: &str) -> Result<Self, SerialError> {
+        parse_json(json, "config")
+    }
+
+    /// IXON/IXOFF/IXANY as given, everything else unchanged.
+    pub fn software_flow(xon: bool, xoff: bool, xany: bool) -> Self {
+        ConfigUpdate {
+            xon: Some(xon),
+            xoff: Some(xoff),
+            xany: Some(xany),
+            ..Default::default()
+        }
+    }
+
+    /// `current` with the read policy fields of this update applied.
+    pub fn read_policy(&self, current: ReadPolicy) -> ReadPolicy {
+        ReadPolicy {
+            min_bytes: self.read_min_bytes.unwrap_or(current.min_bytes),
+            inter_byte_timeout: match self.inter_byte_timeout_us {
+                Some(0) => None,
+                Some(us) => Some(Duration::from_micros(us.into())),
+                None => current.inter_byte_timeout,
+            },
+        }
+    }
+
+    fn has_software_flow(&self) -> bool {
+        self.xon.is_some()
+            || self.xoff.is_some()
+            || self.xany.is_some()
+            || self.xon_char.is_some()
+            || self.xoff_char.is_some()
+    }
+
+    /// Check every field without touching the port.
+    pub fn validate(&self) -> Result<(), SerialError> {
+        if self.baud_rate == Some(0) {
+            return Err(invalid("baudRate must be positive".into()));
+        }
+        self.data_bits.map(data_bits).transpose()?;
+        if self.stop_bits == Some(StopBits::OnePointFive) && self.data_bits.is_some_and(|n| n != 5)
+        {
+            return Err(invalid("stopBits 1.5 requires dataBits 5".into()));
+        }
+        // CSTOPB with CS5 gives 1.5 stop bits on the wire.
+        if self.stop_bits == Some(StopBits::Two) && self.data_bits == Some(5) {
+            return Err(invalid(
+                "stopBits 2 is not available with dataBits 5".into(),
+            ));
+        }
+        if self.xon_char.is_some() && self.xon_char == self.xoff_char {
+            return Err(invalid("xonChar and xoffChar must differ".into()));
+        }
+        if self.read_min_bytes == Some(0) {
+            return Err(invalid("readMinBytes must be at least 1".into()));
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.low_latency.is_some() {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "lowLatency is only available on Linux",
+            ));
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.hupcl.is_some() || self.clocal.is_some() {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "hupcl and clocal are only available on Linux",
+            ));
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.has_software_flow() {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "xon/xoff/xany and custom flow characters are only available on Linux",
+            ));
+        }
+        #[cfg(not(target_os = "linux"))]
+        if matches!(self.parity, Some(Parity::Mark | Parity::Space))
+            || self.stop_bits == Some(StopBits::OnePointFive)
+        {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "mark/space parity and 1.5 stop bits are only available on Linux",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate every field, then apply them, so a bad value changes nothing.
+    /// `on_baud` is told once the baud rate is set, even if a later step
+    /// fails.
+    pub fn apply(&self, port: &mut Port, on_baud: impl FnOnce(u32)) -> Result<(), SerialError> {
+        self.validate()?;
+        let five = port.data_bits()? == serialport::DataBits::Five;
+        if self.data_bits.is_none() {
+            match self.stop_bits {
+                Some(StopBits::OnePointFive) if !five => {
+                    return Err(invalid("stopBits 1.5 requires dataBits 5".into()));
+                }
+                Some(StopBits::Two) if five => {
+                    return Err(invalid(
+                        "stopBits 2 is not available with dataBits 5".into(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        // CSTOPB means 1.5 or 2 stop bits depending on the data bits, so
+        // moving to or from 5 must say which is wanted.
+        if self.stop_bits.is_none()
+            && self.data_bits.is_some_and(|n| (n == 5) != five)
+            && port.stop_bits()? == serialport::StopBits::Two
+        {
+            return Err(invalid(
+                "changing dataBits to or from 5 with more than one stop bit requires stopBits"
+                    .into(),
+            ));
+        }
+        // Only the driver knows whether it has serial_struct (a pty does not).
+        #[cfg(target_os = "linux")]
+        if self.low_latency.is_some() {
+            linux::check_low_latency(port)?;
+        }
+
+        // First, so a failure further down does not hang up the lines when
+        // the port is dropped.
+        #[cfg(target_os = "linux")]
+        if self.hupcl.is_some() || self.clocal.is_some() {
+            linux::set_modem_control(port, self.hupcl, self.clocal)?;
+        }
+        // Before the line settings too, being the other ioctl that may fail.
+        #[cfg(target_os = "linux")]
+        if let Some(on) = self.low_latency {
+            linux::set_low_latency(port, on)?;
+        }
+        if let Some(baud) = self.baud_rate {
+            #[cfg(target_os = "linux")]
+            linux::set_baud_rate(port, baud)?;
+            #[cfg(not(target_os = "linux"))]
+            port.set_baud_rate(baud)?;
+            on_baud(baud);
+        }
+        if let Some(n) = self.data_bits {
+            port.set_data_bits(data_bits(n)?)?;
+        }
+        if let Some(parity) = self.parity {
+            #[cfg(target_os = "linux")]
+            linux::set_parity(port, parity)?;
+            #[cfg(not(target_os = "linux"))]
+            port.set_parity(match parity {
+                Parity::None => serialport::Parity::None,
+                Parity::Odd => serialport::Parity::Odd,
+                Parity::Even => serialport::Parity::Even,
+                Parity::Mark | Parity::Space => unreachable!("rejected by validate"),
+            })?;
+        }
+        if let Some(bits) = self.stop_bits {
+            // With 5 data bits, CSTOPB means 1.5 stop bits.
+            port.set_stop_bits(match bits {
+                StopBits::One => serialport::StopBits::One,
+                StopBits::OnePointFive | StopBits::Two => serialport::StopBits::Two,
+            })?;
+        }
+        if let Some(flow) = self.flow_control {
+            port.set_flow_control(match flow {
+                FlowControl::None => serialport::FlowControl::None,
+                FlowControl::Hardware => serialport::FlowControl::Hardware,
+                FlowControl::Software => serialport::FlowControl::Software,
+            })?;
+        }
+        #[cfg(target_os = "linux")]
+        if self.has_software_flow() {
+            linux::set_software_flow(port, self)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{ConfigUpdate, ModemControl, Parity, SoftwareFlow, StopBits};
+    use crate::{error::SerialError, termios, Port};
+    use std::os::unix::io::AsRawFd;
+
+    pub fn read(port: &Port, data_bits: u8) -> Result<(Parity, StopBits), SerialError> {
+        let t = termios::get(port.as_raw_fd())?;
+        let odd = t.c_cflag & libc::PARODD != 0;
+        let parity = match (t.c_cflag & libc::PARENB != 0, t.c_cflag & libc::CMSPAR != 0) {
+            (false, _) => Parity::None,
+            (true, false) if odd => Parity::Odd,
+            (true, false) => Parity::Even,
+            (true, true) if odd => Parity::Mark,
+            (true, true) => Parity::Space,
+        };
+        let stop_bits = match (t.c_cflag & libc::CSTOPB != 0, data_bits) {
+            (false, _) => StopBits::One,
+            (true, 5) => StopBits::OnePointFive,
+            (true, _) => StopBits::Two,
+        };
+        Ok((parity, stop_bits))
+    }
+
+    pub fn read_software_flow(port: &Port) -> Result<SoftwareFlow, SerialError> {
+        let t = termios::get(port.as_raw_fd())?;
+        Ok(SoftwareFlow {
+            xon: t.c_iflag & libc::IXON != 0,
+            xoff: t.c_iflag & libc::IXOFF != 0,
+            xany: t.c_iflag & libc::IXANY != 0,
+            xon_char: t.c_cc[libc::VSTART],
+            xoff_char: t.c_cc[libc::VSTOP],
+        })
+    }
+
+    pub fn read_modem_control(port: &Port) -> Result<ModemControl, SerialError> {
+        let t = termios::get(port.as_raw_fd())?;
+        Ok(ModemControl {
+            hupcl: t.c_cflag & libc::HUPCL != 0,
+            clocal: t.c_cflag & libc::CLOCAL != 0,
+        })
+    }
+
+    pub fn set_modem_control(
+        port: &Port,
+        hupcl: Option<bool>,
+        clocal: Option<bool>,
+    ) -> Result<(), SerialError> {
+        termios::update(port.as_raw_fd(), |t| {
+            for (flag, on) in [(libc::HUPCL, hupcl), (libc::CLOCAL, clocal)] {
+                match on {
+                    Some(true) => t.c_cflag |= flag,
+                    Some(false) => t.c_cflag &= !flag,
+                    None => {}
+                }
+            }
+        })?;
+        Ok(())
+    }
+
+    // None where the driver has no serial_struct, e.g. a pty.
+    pub fn low_latency(port: &Port) -> Option<bool> {
+        termios::low_latency(port.as_raw_fd()).ok()
+    }
+
+    pub fn check_low_latency(port: &Port) -> Result<(), SerialError> {
+        termios::low_latency(port.as_raw_fd())?;
+        Ok(())
+    }
+
+    pub fn set_low_latency(port: &Port, on: bool) -> Result<(), SerialError> {
+        Ok(termios::set_low_latency(port.as_raw_fd(), on)?)
+    }
+
+    pub fn set_software_flow(port: &Port, update: &ConfigUpdate) -> Result<(), SerialError> {
+        termios::update(port.as_raw_fd(), |t| {
+            for (flag, on) in [
+                (libc::IXON, update.xon),
+                (libc::IXOFF, update.xoff),
+                (libc::IXANY, update.xany),
+            ] {
+                match on {
+                    Some(true) => t.c_iflag |= flag,
+                    Some(false) => t.c_iflag &= !flag,
+                    None => {}
+                }
+            }
+            if let Some(c) = update.xon_char {
+                t.c_cc[libc::VSTART] = c;
+            }
+            if let Some(c) = update.xoff_char {
+                t.c_cc[libc::VSTOP] = c;
+            }
+        })?;
+        Ok(())
+    }
+
+    // Any integer rate via BOTHER; serialport only does this on glibc builds.
+    pub fn set_baud_rate(port: &Port, baud: u32) -> Result<(), SerialError> {
+        termios::update(port.as_raw_fd(), |t| {
+            t.c_cflag &= !(libc::CBAUD | libc::CIBAUD);
+            t.c_cflag |= libc::BOTHER;
+            t.c_ispeed = baud;
+            t.c_ospeed = baud;
+        })?;
+        Ok(())
+    }
+
+    // The driver writes back the rate its divisor actually produces.
+    pub fn baud_rate(port: &Port) -> Result<u32, SerialError> {
+        Ok(termios::get(port.as_raw_fd())?.c_ospeed)
+    }
+
+    // serialport never clears CMSPAR, so all parity modes go through here.
+    pub fn set_parity(port: &Port, parity: Parity) -> Result<(), SerialError> {
+        termios::update(port.as_raw_fd(), |t| {
+            t.c_cflag &= !(libc::PARENB | libc::PARODD | libc::CMSPAR);
+            t.c_cflag |= match parity {
+                Parity::None => 0,
+                Parity::Odd => libc::PARENB | libc::PARODD,
+                Parity::Even => libc::PARENB,
+                Parity::Mark => libc::PARENB | libc::CMSPAR | libc::PARODD,
+                Parity::Space => libc::PARENB | libc::CMSPAR,
+            };
+            if parity == Parity::None {
+                t.c_iflag &= !libc::INPCK;
+                t.c_iflag |= libc::IGNPAR;
+            } else {
+                t.c_iflag |= libc::INPCK;
+                t.c_iflag &= !libc::IGNPAR;
+            }
+        })?;
+        Ok(())
+    }
+}
+
+/// Current schema of `serial_open_ex`. New options are added as optional
+/// fields; the version only changes for incompatible revisions.
+pub const OPEN_OPTIONS_VERSION: u32 = 1;
+
+/// Options of `serial_open_ex`. Unknown fields are rejected.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OpenOptions {
+    pub version: u32,
+    pub path: String,
+    pub baud_rate: u32,
+    data_bits: Option<u8>,
+    parity: Option<Parity>,
+    stop_bits: Option<StopBits>,
+    flow_control: Option<FlowControl>,
+    xon: Option<bool>,
+    xoff: Option<bool>,
+    xany: Option<bool>,
+    xon_char: Option<u8>,
+    xoff_char: Option<u8>,
+    hupcl: Option<bool>,
+    clocal: Option<bool>,
+    low_latency: Option<bool>,
+    read_min_bytes: Option<usize>,
+    inter_byte_timeout_us: Option<u32>,
+    // Line levels set before the handle is returned; absent leaves them as
+    // the driver left them (Linux raises both on open).
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    // -1 (the default) blocks until data arrives or the read is woken.
+    read_timeout_ms: Option<i64>,
+    // Refuse to share the device: TIOCEXCL + flock on Unix, plus no second
+    // handle to it in this process.
+    #[serde(default)]
+    pub exclusive: bool,
+    // Take a UUCP lock file (LCK..<device>) in `lock_dir` (default
+    // /var/lock) for the lifetime of the handle.
+    #[serde(default)]
+    pub lock_file: bool,
+    pub lock_dir: Option<String>,
+    // Snapshot termios and DTR/RTS before opening; put them back on close or
+    // when the library unloads. Linux only.
+    #[serde(default)]
+    pub restore_on_close: bool,
+}
+
+impl OpenOptions {
+    pub fn parse(json: &str) -> Result<Self, SerialError> {
+        let opts: OpenOptions = parse_json(json, "open options")?;
+        if opts.version != OPEN_OPTIONS_VERSION {
+            return Err(invalid(format!(
+                "unsupported options version {} (expected {OPEN_OPTIONS_VERSION})",
+                opts.version
+            )));
+        }
+        if opts.path.is_empty() {
+            return Err(invalid("path must not be empty".into()));
+        }
+        if let Some(ms) = opts.read_timeout_ms {
+            if !(-1..=i64::from(u32::MAX)).contains(&ms) {
+                return Err(invalid(format!("invalid readTimeoutMs {ms}")));
+            }
+        }
+        // The port opens with 8 data bits, so 1.5 must come with an explicit 5.
+        if opts.stop_bits == Some(StopBits::OnePointFive) && opts.data_bits != Some(5) {
+            return Err(invalid("stopBits 1.5 requires dataBits 5".into()));
+        }
+        if opts.lock_dir.is_some() && !opts.lock_file {
+            return Err(invalid("lockDir requires lockFile".into()));
+        }
+        if cfg!(not(target_os = "linux")) && opts.restore_on_close {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "restoreOnClose is only supported on Linux",
+            ));
+        }
+        if cfg!(not(unix)) && opts.lock_file {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "lock files are only supported on Unix",
+            ));
+        }
+        opts.line_settings().validate()?;
+        Ok(opts)
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        match self.read_timeout_ms {
+            Some(ms) if ms >= 0 => Some(Duration::from_millis(ms as u64)),
+            _ => None,
+        }
+    }
+
+    /// Settings applied once the port is open.
+    pub fn line_settings(&self) -> ConfigUpdate {
+        ConfigUpdate {
+            baud_rate: Some(self.baud_rate),
+            data_bits: self.data_bits,
+            parity: self.parity,
+            stop_bits: self.stop_bits,
+            flow_control: self.flow_control,
+            xon: self.xon,
+            xoff: self.xoff,
+            xany: self.xany,
+            xon_char: self.xon_char,
+            xoff_char: self.xoff_char,
+            hupcl: self.hupcl,
+            clocal: self.clocal,
+            low_latency: self.low_latency,
+            read_min_bytes: self.read_min_bytes,
+            inter_byte_timeout_us: self.inter_byte_timeout_us,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn open(extra: serde_json::Value) -> Result<OpenOptions, SerialError> {
+        let mut opts = json!({ "version": 1, "path": "/dev/ttyS0", "baudRate": 9600 });
+        opts.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        OpenOptions::parse(&opts.to_string())
+    }
+
+    fn kind<T>(res: Result<T, SerialError>) -> ErrorKind {
+        res.err().expect("should fail").kind
+    }
+
+    #[test]
+    fn minimal_options() {
+        let opts = open(json!({})).unwrap();
+        assert_eq!(opts.baud_rate, 9600);
+        assert!(!opts.exclusive);
+        assert_eq!(opts.read_timeout(), None);
+    }
+
+    #[test]
+    fn unknown_keys_rejected() {
+        assert_eq!(
+            kind(open(json!({ "baud": 9600 }))),
+            ErrorKind::InvalidConfig
+        );
+        assert_eq!(
+            kind(ConfigUpdate::parse(r#"{"effectiveBaudRate":9600}"#)),
+            ErrorKind::InvalidConfig
+        );
+    }
+
+    #[test]
+    fn version_checked() {
+        assert_eq!(
+            kind(open(json!({ "version": 2 }))),
+            ErrorKind::InvalidConfig
+        );
+        assert_eq!(
+            kind(open(json!({ "version": 0 }))),
+            ErrorKind::InvalidConfig
+        );
+        let res = OpenOptions::parse(r#"{"path":"/dev/ttyS0","baudRate":9600}"#);
+        assert_eq!(kind(res), ErrorKind::InvalidConfig);
+    }
+
+    #[test]
+    fn data_and_stop_bits_checked() {
+        for bits in [4, 9] {
+            let res = open(json!({ "dataBits": bits }));
+            assert_eq!(kind(res), ErrorKind::InvalidConfig);
+        }
+        for bits in [json!(0), json!(3), json!(1.25)] {
+            let res = open(json!({ "stopBits": bits }));
+            assert_eq!(kind(res), ErrorKind::InvalidConfig);
+        }
+        #[cfg(target_os = "linux")]
+        assert!(open(json!({ "dataBits": 5, "stopBits": 1.5 })).is_ok());
+        let res = open(json!({ "dataBits": 8, "stopBits": 1.5 }));
+        assert_eq!(kind(res), ErrorKind::InvalidConfig);
+        let res =
+            ConfigUpdate::parse(r#"{"dataBits":7,"stopBits":1.5}"#).and_then(|u| u.validate());
+        assert_eq!(kind(res), ErrorKind::InvalidConfig);
+        let res = open(json!({ "dataBits": 5, "stopBits": 2 }));
+        assert_eq!(kind(res), ErrorKind::InvalidConfig);
+        assert!(open(json!({ "dataBits": 6, "stopBits": 2 })).is_ok());
+    }
+
+    #[test]
+    fn read_timeout_range() {
+        assert_eq!(
+            open(json!({ "readTimeoutMs": 250 }))
+                .unwrap()
+                .read_timeout(),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            open(json!({ "readTimeoutMs": -1 })).unwrap().read_timeout(),
+            None
+        );
+        let res = open(json!({ "readTimeoutMs": -2 }));
+        assert_eq!(kind(res), ErrorKind::InvalidConfig);
+    }
+
+    // What serial_get_config reports is accepted back by serial_set_config.
+    // Linux, where every field is reported and settable.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn config_round_trip() {
+        let config = Config {
+            baud_rate: 115_200,
+            effective_baud_rate: 115_384,
+            data_bits: 5,
+            parity: Parity::Mark,
+            stop_bits: StopBits::OnePointFive,
+            flow_control: FlowControl::Software,
+            software_flow: Some(SoftwareFlow {
+                xon: true,
+                xoff: false,
+                xany: true,
+                xon_char: 0x11,
+                xoff_char: 0x13,
+            }),
+            modem_control: Some(ModemControl {
+                hupcl: false,
+                clocal: true,
+            }),
+            low_latency: Some(true),
+            read_min_bytes: 4,
+            inter_byte_timeout_us: 1500,
+        };
+        let mut value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["stopBits"], json!(1.5));
+        assert_eq!(value["effectiveBaudRate"], json!(115_384));
+        value.as_object_mut().unwrap().remove("effectiveBaudRate");
+        let update = ConfigUpdate::parse(&value.to_string()).unwrap();
+        update.validate().unwrap();
+        assert_eq!(update.baud_rate, Some(115_200));
+        assert_eq!(update.data_bits, Some(5));
+        assert_eq!(update.parity, Some(Parity::Mark));
+        assert_eq!(update.stop_bits, Some(StopBits::OnePointFive));
+        assert_eq!(update.flow_control, Some(FlowControl::Software));
+        assert_eq!(
+            (update.xon, update.xoff, update.xany),
+            (Some(true), Some(false), Some(true))
+        );
+        assert_eq!(
+            (update.xon_char, update.xoff_char),
+            (Some(0x11), Some(0x13))
+        );
+        assert_eq!((update.hupcl, update.clocal), (Some(false), Some(true)));
+        assert_eq!(update.low_latency, Some(true));
+        let policy = update.read_policy(ReadPolicy::default());
+        assert_eq!(policy.min_bytes, 4);
+        assert_eq!(policy.inter_byte_timeout, Some(Duration::from_micros(1500)));
+    }
+}
@@ -0,0 +1,297 @@
+//! Error taxonomy and last-error bookkeeping for the C ABI
+use serde::Serialize;
+use std::{cell::RefCell, io, os::raw::c_int};
+use thiserror::Error;
+
+// Negative return codes, one per kind. Keep in sync with `ErrorCode` in ffi.ts.
+pub const ERR_IO: c_int = -1;
+pub const ERR_NOT_FOUND: c_int = -2;
+pub const ERR_PERMISSION_DENIED: c_int = -3;
+pub const ERR_BUSY: c_int = -4;
+pub const ERR_DISCONNECTED: c_int = -5;
+pub const ERR_TIMED_OUT: c_int = -6;
+pub const ERR_INVALID_CONFIG: c_int = -7;
+pub const ERR_UNSUPPORTED: c_int = -8;
+pub const ERR_INVALID_HANDLE: c_int = -9;
+pub const ERR_STALE_HANDLE: c_int = -10;
+pub const ERR_CANCELLED: c_int = -11;
+pub const ERR_WOULD_BLOCK: c_int = -12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    Busy,
+    Disconnected,
+    TimedOut,
+    InvalidConfig,
+    Unsupported,
+    InvalidHandle,
+    StaleHandle,
+    Cancelled,
+    WouldBlock,
+    Io,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> c_int {
+        match self {
+            ErrorKind::NotFound => ERR_NOT_FOUND,
+            ErrorKind::PermissionDenied => ERR_PERMISSION_DENIED,
+            ErrorKind::Busy => ERR_BUSY,
+            ErrorKind::Disconnected => ERR_DISCONNECTED,
+            ErrorKind::TimedOut => ERR_TIMED_OUT,
+            ErrorKind::InvalidConfig => ERR_INVALID_CONFIG,
+            ErrorKind::Unsupported => ERR_UNSUPPORTED,
+            ErrorKind::InvalidHandle => ERR_INVALID_HANDLE,
+            ErrorKind::StaleHandle => ERR_STALE_HANDLE,
+            ErrorKind::Cancelled => ERR_CANCELLED,
+            ErrorKind::WouldBlock => ERR_WOULD_BLOCK,
+            ErrorKind::Io => ERR_IO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("{message}")]
+pub struct SerialError {
+    pub kind: ErrorKind,
+    pub errno: Option<i32>,
+    pub message: String,
+}
+
+impl SerialError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        SerialError {
+            kind,
+            errno: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<io::Error> for SerialError {
+    fn from(e: io::Error) -> Self {
+        let errno = e.raw_os_error();
+        let kind = errno
+            .and_then(kind_from_errno)
+            .unwrap_or_else(|| kind_from_io(e.kind()));
+        SerialError {
+            kind,
+            errno,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<serialport::Error> for SerialError {
+    fn from(e: serialport::Error) -> Self {
+        // serialport keeps only the message; recover the OS error from it so
+        // e.g. a port another process holds with TIOCEXCL (EBUSY) is Busy.
+        if let Some(errno) = errno_from_message(&e.description) {
+            let mut err = SerialError::from(io::Error::from_raw_os_error(errno));
+            err.message = e.description;
+            return err;
+        }
+        let kind = match e.kind {
+            serialport::ErrorKind::NoDevice => ErrorKind::NotFound,
+            serialport::ErrorKind::InvalidInput => ErrorKind::InvalidConfig,
+            serialport::ErrorKind::Unknown => ErrorKind::Io,
+            serialport::ErrorKind::Io(k) => kind_from_io(k),
+        };
+        SerialError::new(kind, e.description)
+    }
+}
+
+fn errno_from_message(message: &str) -> Option<i32> {
+    // Converted io::Error: "<text> (os error N)".
+    let from_io = message
+        .strip_suffix(')')
+        .and_then(|m| m.rsplit_once(" (os error "))
+        .and_then(|(_, n)| n.parse().ok());
+    from_io.or_else(|| errno_from_description(message))
+}
+
+// serialport builds its Unix errors from nix's Errno::desc().
+#[cfg(unix)]
+fn errno_from_description(message: &str) -> Option<i32> {
+    (1..256).find(|&n| {
+        let errno = nix::errno::Errno::from_i32(n);
+        errno != nix::errno::Errno::UnknownErrno && errno.desc() == message
+    })
+}
+
+// serialport formats Windows errors as std does, without the code.
+#[cfg(windows)]
+fn errno_from_description(message: &str) -> Option<i32> {
+    const CANDIDATES: [i32; 9] = [2, 3, 5, 22, 31, 32, 87, 995, 1167];
+    CANDIDATES.into_iter().find(|&n| {
+        io::Error::from_raw_os_error(n).to_string() == format!("{message} (os error {n})")
+    })
+}
+
+fn kind_from_io(k: io::ErrorKind) -> ErrorKind {
+    use io::ErrorKind as K;
+    match k {
+        K::NotFound => ErrorKind::NotFound,
+        K::PermissionDenied => ErrorKind::PermissionDenied,
+        K::TimedOut => ErrorKind::TimedOut,
+        K::BrokenPipe | K::UnexpectedEof | K::ConnectionReset | K::ConnectionAborted => {
+            ErrorKind::Disconnected
+        }
+        K::InvalidInput => ErrorKind::InvalidConfig,
+        K::Unsupported => ErrorKind::Unsupported,
+        K::Interrupted => ErrorKind::Cancelled,
+        K::WouldBlock => ErrorKind::WouldBlock,
+        _ => ErrorKind::Io,
+    }
+}
+
+#[cfg(unix)]
+fn kind_from_errno(errno: i32) -> Option<ErrorKind> {
+    // Values shared by Linux and the BSDs/macOS.
+    const EIO: i32 = 5;
+    const ENXIO: i32 = 6;
+    const EBUSY: i32 = 16;
+    const ENODEV: i32 = 19;
+    const ENOTTY: i32 = 25;
+    match errno {
+        EBUSY => Some(ErrorKind::Busy),
+        // The driver does not implement the requested ioctl.
+        ENOTTY => Some(ErrorKind::Unsupported),
+        ENXIO | ENODEV => Some(ErrorKind::NotFound),
+        // A tty whose device went away (or whose pty master closed) fails
+        // every read/write with EIO.
+        EIO => Some(ErrorKind::Disconnected),
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+fn kind_from_errno(errno: i32) -> Option<ErrorKind> {
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    const ERROR_BAD_COMMAND: i32 = 22;
+    const ERROR_GEN_FAILURE: i32 = 31;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_OPERATION_ABORTED: i32 = 995;
+    const ERROR_DEVICE_NOT_CONNECTED: i32 = 1167;
+    match errno {
+        // COM ports are exclusive; a second open fails with access denied.
+        ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION => Some(ErrorKind::Busy),
+        ERROR_BAD_COMMAND | ERROR_GEN_FAILURE | ERROR_DEVICE_NOT_CONNECTED => {
+            Some(ErrorKind::Disconnected)
+        }
+        ERROR_OPERATION_ABORTED => Some(ErrorKind::Cancelled),
+        _ => None,
+    }
+}
+
+/// A failure together with where it happened, as reported by `serial_err_json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub kind: ErrorKind,
+    pub code: c_int,
+    pub errno: Option<i32>,
+    pub operation: &'static str,
+    pub path: Option<String>,
+    pub message: String,
+}
+
+impl ErrorRecord {
+    pub fn new(operation: &'static str, path: Option<&str>, e: SerialError) -> Self {
+        ErrorRecord {
+            kind: e.kind,
+            code: e.kind.code(),
+            errno: e.errno,
+            operation,
+            path: path.map(str::to_owned),
+            message: e.message,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+thread_local! {
+    // Per thread so concurrent failures on different threads don't clobber
+    // each other.
+    static LAST_ERROR: RefCell<Option<ErrorRecord>> = const { RefCell::new(None) };
+}
+
+pub fn set_last(record: ErrorRecord) -> c_int {
+    let code = record.code;
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(record));
+    code
+}
+
+pub fn with_last<R>(f: impl FnOnce(Option<&ErrorRecord>) -> R) -> R {
+    LAST_ERROR.with(|e| f(e.borrow().as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_serialport(desc: &str) -> SerialError {
+        serialport::Error::new(serialport::ErrorKind::Unknown, desc).into()
+    }
+
+    #[test]
+    fn errno_from_os_error_suffix() {
+        let e = from_serialport("Device or resource busy (os error 16)");
+        assert_eq!(e.errno, Some(16));
+        assert_eq!(e.message, "Device or resource busy (os error 16)");
+        #[cfg(unix)]
+        assert_eq!(e.kind, ErrorKind::Busy);
+    }
+
+    // The exact texts serialport produces through nix's Errno::desc(); if a
+    // serialport or nix upgrade changes them, these stop matching.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn errno_from_nix_description() {
+        for (desc, errno, kind) in [
+            ("Device or resource busy", 16, ErrorKind::Busy),
+            ("No such file or directory", 2, ErrorKind::NotFound),
+            ("Permission denied", 13, ErrorKind::PermissionDenied),
+            ("Not a typewriter", 25, ErrorKind::Unsupported),
+            ("No such device", 19, ErrorKind::NotFound),
+        ] {
+            let e = from_serialport(desc);
+            assert_eq!((e.errno, e.kind), (Some(errno), kind), "{desc}");
+            assert_eq!(e.message, desc);
+        }
+    }
+
+    #[test]
+    fn unknown_message_keeps_serialport_kind() {
+        let e: SerialError =
+            serialport::Error::new(serialport::ErrorKind::NoDevice, "gone for lunch").into();
+        assert_eq!((e.errno, e.kind), (None, ErrorKind::NotFound));
+        let e = from_serialport("os error but not really");
+        assert_eq!((e.errno, e.kind), (None, ErrorKind::Io));
+    }
+
+    // Through serialport's own error conversion, whatever nix it uses.
+    #[cfg(unix)]
+    #[test]
+    fn errno_from_real_open_failures() {
+        let err = |path| {
+            SerialError::from(
+                serialport::new(path, 9600)
+                    .open_native()
+                    .expect_err("should fail"),
+            )
+        };
+        let e = err("/nonexistent/ttyS0");
+        assert_eq!((e.errno, e.kind), (Some(libc::ENOENT), ErrorKind::NotFound));
+        // Not a tty: the termios calls fail with ENOTTY.
+        let e = err("/dev/null");
+        assert_eq!(
+            (e.errno, e.kind),
+            (Some(libc::ENOTTY), ErrorKind::Unsupported)
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Modem-line and line-error event detection for `serial_wait_event`
+//!
+//! On Linux, waits for modem lines only block in TIOCMIWAIT. That ioctl can
+//! neither time out nor be woken without a signal, so it runs on a helper
+//! thread that is interrupted with one. Line errors, other platforms and
+//! drivers without TIOCMIWAIT fall back to sampling the port on a short
+//! interval. Where the TIOCGICOUNT counters exist, they latch every
+//! transition and error, so pulses shorter than the interval are still
+//! reported.
+use crate::{error::SerialError, Port};
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::{
+    io::{self, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+        thread::JoinHandleExt,
+    },
+    thread,
+};
+
+// Same bit layout as serial_get_lines for the modem lines.
+pub const EV_CTS: u32 = 1 << 0;
+pub const EV_DSR: u32 = 1 << 1;
+pub const EV_DCD: u32 = 1 << 2;
+pub const EV_RI: u32 = 1 << 3;
+pub const EV_BREAK: u32 = 1 << 4;
+pub const EV_FRAME: u32 = 1 << 5;
+pub const EV_PARITY: u32 = 1 << 6;
+pub const EV_OVERRUN: u32 = 1 << 7;
+
+pub const EV_MODEM: u32 = EV_CTS | EV_DSR | EV_DCD | EV_RI;
+pub const EV_LINE_ERRORS: u32 = EV_BREAK | EV_FRAME | EV_PARITY | EV_OVERRUN;
+pub const EV_ALL: u32 = EV_MODEM | EV_LINE_ERRORS;
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// How long after starting TIOCMIWAIT the lines are sampled once, to catch a
+// change between the caller's snapshot and the helper entering the ioctl.
+#[cfg(target_os = "linux")]
+pub const ARM_CHECK: Duration = Duration::from_millis(10);
+
+/// Kernel transition/error counters (Linux `struct serial_icounter_struct`).
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    cts: i32,
+    dsr: i32,
+    rng: i32,
+    dcd: i32,
+    rx: i32,
+    tx: i32,
+    frame: i32,
+    overrun: i32,
+    parity: i32,
+    brk: i32,
+    buf_overrun: i32,
+    reserved: [i32; 9],
+}
+
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    lines: u32,
+    #[cfg(target_os = "linux")]
+    counters: Option<Counters>,
+}
+
+impl Snapshot {
+    pub fn take(port: &mut Port) -> Result<Self, SerialError> {
+        Ok(Snapshot {
+            lines: modem_lines(port)?,
+            #[cfg(target_os = "linux")]
+            counters: counters(port),
+        })
+    }
+
+    /// Events this port can report.
+    pub fn supported(&self) -> u32 {
+        #[cfg(target_os = "linux")]
+        if self.counters.is_some() {
+            return EV_ALL;
+        }
+        EV_MODEM
+    }
+
+    /// Events that happened between `earlier` and this snapshot.
+    pub fn events_since(&self, earlier: &Snapshot) -> u32 {
+        #[allow(unused_mut)]
+        let mut ev = (self.lines ^ earlier.lines) & EV_MODEM;
+        #[cfg(target_os = "linux")]
+        if let (Some(a), Some(b)) = (earlier.counters, self.counters) {
+            let bump = |x: i32, y: i32, bit: u32| if x != y { bit } else { 0 };
+            ev |= bump(a.cts, b.cts, EV_CTS)
+                | bump(a.dsr, b.dsr, EV_DSR)
+                | bump(a.dcd, b.dcd, EV_DCD)
+                | bump(a.rng, b.rng, EV_RI)
+                | bump(a.brk, b.brk, EV_BREAK)
+                | bump(a.frame, b.frame, EV_FRAME)
+                | bump(a.parity, b.parity, EV_PARITY)
+                | bump(
+                    a.overrun + a.buf_overrun,
+                    b.overrun + b.buf_overrun,
+                    EV_OVERRUN,
+                );
+        }
+        ev
+    }
+}
+
+#[cfg(unix)]
+fn modem_lines(port: &Port) -> Result<u32, SerialError> {
+    use std::os::unix::io::AsRawFd;
+    let mut bits: libc::c_int = 0;
+    if unsafe { libc::ioctl(port.as_raw_fd(), libc::TIOCMGET, &mut bits) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut lines = 0;
+    for (tiocm, ev) in [
+        (libc::TIOCM_CTS, EV_CTS),
+        (libc::TIOCM_DSR, EV_DSR),
+        (libc::TIOCM_CAR, EV_DCD),
+        (libc::TIOCM_RNG, EV_RI),
+    ] {
+        if bits & tiocm != 0 {
+            lines |= ev;
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(windows)]
+fn modem_lines(port: &mut Port) -> Result<u32, SerialError> {
+    use serialport::SerialPort;
+    let mut lines = 0;
+    if port.read_clear_to_send()? {
+        lines |= EV_CTS;
+    }
+    if port.read_data_set_ready()? {
+        lines |= EV_DSR;
+    }
+    if port.read_carrier_detect()? {
+        lines |= EV_DCD;
+    }
+    if port.read_ring_indicator()? {
+        lines |= EV_RI;
+    }
+    Ok(lines)
+}
+
+/// A helper thread blocked in TIOCMIWAIT (Linux).
+#[cfg(target_os = "linux")]
+pub struct ModemWait {
+    helper: thread::JoinHandle<io::Result<()>>,
+    // Readable once the helper is done.
+    done: UnixStream,
+}
+
+#[cfg(target_os = "linux")]
+impl ModemWait {
+    /// Start waiting for a change of the modem lines in `mask` on `fd`. None
+    /// if the signal that interrupts the helper is taken by someone else.
+    pub fn start(fd: RawFd, mask: u32) -> io::Result<Option<Self>> {
+        if interrupt_signal().is_none() {
+            return Ok(None);
+        }
+        let mut bits: libc::c_int = 0;
+        for (ev, tiocm) in [
+            (EV_CTS, libc::TIOCM_CTS),
+            (EV_DSR, libc::TIOCM_DSR),
+            (EV_DCD, libc::TIOCM_CAR),
+            (EV_RI, libc::TIOCM_RNG),
+        ] {
+            if mask & ev != 0 {
+                bits |= tiocm;
+            }
+        }
+        let (done, done_tx) = UnixStream::pair()?;
+        let helper = thread::Builder::new()
+            .name("serial-miwait".into())
+            .spawn(move || {
+                let rc = unsafe { libc::ioctl(fd, libc::TIOCMIWAIT, bits as libc::c_ulong) };
+                let res = if rc < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                };
+                let _ = (&done_tx).write_all(&[1]);
+                res
+            })?;
+        Ok(Some(ModemWait { helper, done }))
+    }
+
+    /// Wait up to `timeout` (None: forever) for the ioctl to return; false
+    /// if it is still blocked. Fails with `Interrupted` when `waker` fires.
+    pub fn wait(&self, waker: &crate::sys::Waker, timeout: Option<Duration>) -> io::Result<bool> {
+        match crate::sys::wait_fd(self.done.as_raw_fd(), libc::POLLIN, waker, timeout) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Interrupt the ioctl if it is still blocked and return how it ended
+    /// (EINTR when interrupted here).
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(sig) = interrupt_signal() {
+            let thread = self.helper.as_pthread_t();
+            // Repeated: a signal that lands just before the helper enters the
+            // ioctl does not stop it.
+            while !self.helper.is_finished() {
+                unsafe {
+                    libc::pthread_kill(thread, sig);
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        self.helper
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("TIOCMIWAIT helper panicked")))
+    }
+}
+
+// The signal that interrupts ModemWait helpers: SIGRTMAX, given a handler
+// without SA_RESTART so the ioctl fails with EINTR. None if the host already
+// handles it.
+#[cfg(target_os = "linux")]
+fn interrupt_signal() -> Option<libc::c_int> {
+    extern "C" fn ignore(_: libc::c_int) {}
+    static SIGNAL: once_cell::sync::Lazy<Option<libc::c_int>> = once_cell::sync::Lazy::new(|| {
+        let sig = libc::SIGRTMAX();
+        unsafe {
+            let mut old: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(sig, std::ptr::null(), &mut old) != 0
+                || old.sa_sigaction != libc::SIG_DFL
+            {
+                return None;
+            }
+            let mut sa: libc::sigaction = std::mem::zeroed();
+            sa.sa_sigaction = ignore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut sa.sa_mask);
+            (libc::sigaction(sig, &sa, std::ptr::null_mut()) == 0).then_some(sig)
+        }
+    });
+    *SIGNAL
+}
+
+// None when the driver has no counters (e.g. ptys, some USB adapters).
+#[cfg(target_os = "linux")]
+fn counters(port: &Port) -> Option<Counters> {
+    use std::os::unix::io::AsRawFd;
+    let mut c = Counters::default();
+    let rc = unsafe { libc::ioctl(port.as_raw_fd(), libc::TIOCGICOUNT, &mut c) };
+    (rc == 0).then_some(c)
+}
@@ -1,29 +1,63 @@
 //! FFI for Deno serial: blocking serialport-rs + C ABI
+// The exported functions take raw pointers straight from Deno FFI; each one
+// null-checks what it dereferences instead of being marked `unsafe`.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+mod config;
+mod error;
+mod events;
+#[cfg(unix)]
+mod lockfile;
+#[cfg(target_os = "linux")]
+mod reactor;
+mod readahead;
+#[cfg(target_os = "linux")]
+mod restore;
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod ring;
+#[cfg(target_os = "linux")]
+mod termios;
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(unix)]
+use unix as sys;
+#[cfg(windows)]
+use windows as sys;
+
+use error::{ErrorKind, ErrorRecord, SerialError};
 use once_cell::sync::Lazy;
+use serialport::SerialPort;
 use slab::Slab;
 use std::{
-    ffi::{CStr, CString},
-    io::{Read, Write},
+    ffi::{c_void, CStr, CString},
     os::raw::{c_char, c_int},
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     thread,
     time::{Duration, Instant},
 };
 
-static LAST_ERROR: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
-fn set_err<E: std::fmt::Display>(e: E) -> c_int {
-    *LAST_ERROR.lock().unwrap() = Some(e.to_string());
-    -1
+// Record `e` as this thread's last error and return its negative code.
+fn set_err(operation: &'static str, path: Option<&str>, e: impl Into<SerialError>) -> c_int {
+    error::set_last(ErrorRecord::new(operation, path, e.into()))
+}
+
+fn invalid_arg(operation: &'static str, what: &str) -> c_int {
+    set_err(
+        operation,
+        None,
+        SerialError::new(ErrorKind::InvalidConfig, what),
+    )
 }
 
+// Human-readable message of this thread's last error.
 #[no_mangle]
 pub extern "C" fn serial_err_len() -> usize {
-    LAST_ERROR
-        .lock()
-        .unwrap()
-        .as_ref()
-        .map(|s| s.len() + 1)
-        .unwrap_or(0)
+    error::with_last(|e| e.map(|e| e.message.len() + 1).unwrap_or(0))
 }
 
 #[no_mangle]
@@ -31,42 +65,379 @@ pub extern "C" fn serial_err_fill(out: *mut u8, len: usize) {
     if out.is_null() || len == 0 {
         return;
     }
-    if let Some(s) = LAST_ERROR.lock().unwrap().as_ref() {
-        let bytes = s.as_bytes();
-        let n = bytes.len().min(len - 1);
-        unsafe {
-            std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, n);
-            *out.add(n) = 0;
+    error::with_last(|e| {
+        if let Some(e) = e {
+            let bytes = e.message.as_bytes();
+            let n = bytes.len().min(len - 1);
+            unsafe {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, n);
+                *out.add(n) = 0;
+            }
         }
+    })
+}
+
+// This thread's last error as JSON ({kind, code, errno, operation, path,
+// message}), or null if none. Free with `serial_free_cstr`.
+#[no_mangle]
+pub extern "C" fn serial_err_json() -> *mut c_char {
+    error::with_last(|e| match e {
+        Some(e) => json_cstr(e.to_json()),
+        None => std::ptr::null_mut(),
+    })
+}
+
+// Nonblocking calls run on a worker thread, so their errors are also kept on
+// the handle. which: 1=read, 2=write, 4=wait_event, 8=control (e.g.
+// serial_send_break). Free with `serial_free_cstr`.
+#[no_mangle]
+pub extern "C" fn serial_io_err_json(h: u64, which: c_int) -> *mut c_char {
+    let half = match which {
+        CANCEL_READ => Half::Read,
+        CANCEL_WRITE => Half::Write,
+        CANCEL_EVENT => Half::Event,
+        IO_ERR_CONTROL => Half::Control,
+        _ => return std::ptr::null_mut(),
+    };
+    let state = match get_state(h, "io_err") {
+        Ok(s) => s,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let record = state.last_error(half).lock().unwrap().clone();
+    match record {
+        Some(e) => json_cstr(e.to_json()),
+        None => std::ptr::null_mut(),
     }
 }
 
+fn json_cstr(json: String) -> *mut c_char {
+    CString::new(json)
+        .unwrap_or_else(|_| CString::new("{}").unwrap())
+        .into_raw()
+}
+
+#[cfg(unix)]
+type Port = serialport::TTYPort;
+#[cfg(windows)]
+type Port = serialport::COMPort;
+
+struct Reader {
+    port: Port,
+    // None blocks until data arrives or the read is woken.
+    timeout: Option<Duration>,
+}
+
+// Each half is an independent clone of the same device so a read blocked in
+// its timeout does not hold up writes or modem-line control on that port.
 struct PortState {
-    port: Box<dyn serialport::SerialPort + Send>,
+    path: String,
+    // See device_key; with `exclusive`, used to refuse a second open of the
+    // same device in this process.
+    device: String,
+    exclusive: bool,
+    // Last rate asked for; serial_get_config reports it next to the rate the
+    // driver settled on.
+    requested_baud: AtomicU32,
+    // Separate from `reader`, which a blocked read holds.
+    read_policy: Mutex<config::ReadPolicy>,
+    reader: Mutex<Reader>,
+    // Received bytes not handed out yet (serial_peek, serial_unread, bytes
+    // past a serial_read_until delimiter, or read by a call that failed).
+    // Every read variant returns these first.
+    read_ahead: Mutex<readahead::ReadAhead>,
+    writer: Mutex<Port>,
+    control: Mutex<Port>,
+    // Wake a reader/writer/event waiter blocked on this port (serial_cancel,
+    // close).
+    read_waker: sys::Waker,
+    write_waker: sys::Waker,
+    event_waker: sys::Waker,
+    read_error: Mutex<Option<ErrorRecord>>,
+    write_error: Mutex<Option<ErrorRecord>>,
+    event_error: Mutex<Option<ErrorRecord>>,
+    control_error: Mutex<Option<ErrorRecord>>,
+    // Set while the reactor drains this port (serial_set_rx_buffer); reads
+    // are then served from the ring instead of the device.
+    rx: Mutex<Option<ring::RxRing>>,
+    // Set by serial_set_data_callback; takes received data instead of `rx`.
+    #[cfg(target_os = "linux")]
+    callback: Mutex<Option<reactor::Callback>>,
+    // Reader fd, registered with the reactor's epoll set.
+    #[cfg(target_os = "linux")]
+    rx_fd: std::os::unix::io::RawFd,
+    // Settings from before the open, put back when the port is dropped or
+    // the library unloads (restoreOnClose).
+    #[cfg(target_os = "linux")]
+    saved: Mutex<Option<restore::Saved>>,
+    // Declared after the ports so the device is closed before the lock goes.
+    #[cfg(unix)]
+    _lock_file: Option<lockfile::LockFile>,
+}
+
+// What the open call decided besides the device itself.
+struct PortSetup {
+    baud: u32,
+    read_timeout: Option<Duration>,
+    read_policy: config::ReadPolicy,
+    exclusive: bool,
+    #[cfg(unix)]
+    lock_file: Option<lockfile::LockFile>,
+    #[cfg(target_os = "linux")]
+    saved: Option<restore::Saved>,
+}
+
+// Runs before the fields (and so the device) are closed.
+#[cfg(target_os = "linux")]
+impl Drop for PortState {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+const CANCEL_READ: c_int = 1;
+const CANCEL_WRITE: c_int = 2;
+const CANCEL_EVENT: c_int = 4;
+const CANCEL_ALL: c_int = CANCEL_READ | CANCEL_WRITE | CANCEL_EVENT;
+// serial_io_err_json selector for control calls, which cannot be cancelled.
+const IO_ERR_CONTROL: c_int = 8;
+
+#[derive(Clone, Copy)]
+enum Half {
+    Read,
+    Write,
+    Control,
+    // serial_wait_event; uses the control port but has its own error slot.
+    Event,
+}
+
+impl PortState {
+    fn new(path: &str, port: Port, setup: PortSetup) -> Result<Self, SerialError> {
+        let writer = port.try_clone_native()?;
+        let control = port.try_clone_native()?;
+        #[cfg(unix)]
+        sys::set_nonblocking(&port)?;
+        #[cfg(unix)]
+        let (read_waker, write_waker, event_waker) =
+            (sys::Waker::new()?, sys::Waker::new()?, sys::Waker::new()?);
+        #[cfg(windows)]
+        let (read_waker, write_waker, event_waker) = (
+            sys::Waker::new(&port),
+            sys::Waker::new(&writer),
+            sys::Waker::new(&control),
+        );
+        #[cfg(target_os = "linux")]
+        let rx_fd = std::os::unix::io::AsRawFd::as_raw_fd(&port);
+        Ok(PortState {
+            path: path.to_owned(),
+            device: device_key(path),
+            exclusive: setup.exclusive,
+            requested_baud: AtomicU32::new(setup.baud),
+            read_policy: Mutex::new(setup.read_policy),
+            reader: Mutex::new(Reader {
+                port,
+                timeout: setup.read_timeout,
+            }),
+            read_ahead: Mutex::new(Default::default()),
+            writer: Mutex::new(writer),
+            control: Mutex::new(control),
+            read_waker,
+            write_waker,
+            event_waker,
+            read_error: Mutex::new(None),
+            write_error: Mutex::new(None),
+            event_error: Mutex::new(None),
+            control_error: Mutex::new(None),
+            rx: Mutex::new(None),
+            #[cfg(target_os = "linux")]
+            callback: Mutex::new(None),
+            #[cfg(target_os = "linux")]
+            rx_fd,
+            #[cfg(target_os = "linux")]
+            saved: Mutex::new(setup.saved),
+            #[cfg(unix)]
+            _lock_file: setup.lock_file,
+        })
+    }
+
+    // Once only: whichever of close and exit gets here first.
+    #[cfg(target_os = "linux")]
+    fn restore(&self) {
+        if let Some(saved) = self.saved.lock().unwrap().take() {
+            let _ = saved.restore(self.rx_fd);
+        }
+    }
+
+    fn wake(&self, which: c_int) {
+        if which & CANCEL_READ != 0 {
+            self.read_waker.wake();
+        }
+        if which & CANCEL_WRITE != 0 {
+            self.write_waker.wake();
+        }
+        if which & CANCEL_EVENT != 0 {
+            self.event_waker.wake();
+        }
+    }
+
+    // The waker serial_cancel uses for calls on `half`, if any.
+    fn waker(&self, half: Half) -> Option<&sys::Waker> {
+        match half {
+            Half::Read => Some(&self.read_waker),
+            Half::Write => Some(&self.write_waker),
+            Half::Event => Some(&self.event_waker),
+            Half::Control => None,
+        }
+    }
+
+    // Each half has its own slot, so e.g. a failed break and a concurrent
+    // failed write do not overwrite each other's detail.
+    fn last_error(&self, half: Half) -> &Mutex<Option<ErrorRecord>> {
+        match half {
+            Half::Read => &self.read_error,
+            Half::Write => &self.write_error,
+            Half::Control => &self.control_error,
+            Half::Event => &self.event_error,
+        }
+    }
+}
+
+struct Entry {
+    generation: u32,
+    state: Arc<PortState>,
 }
 
-static HANDLES: Lazy<Mutex<Slab<PortState>>> = Lazy::new(|| Mutex::new(Slab::new()));
-static mut LAST_HANDLE: u64 = 0;
+// The table lock is only held for lookup/insert/remove, never across I/O.
+static HANDLES: Lazy<Mutex<Slab<Entry>>> = Lazy::new(|| Mutex::new(Slab::new()));
+// Handles are `generation << 32 | slab index`. Generation 0 is never issued.
+static NEXT_GENERATION: AtomicU32 = AtomicU32::new(1);
+// Only kept for the deprecated `serial_last_handle` shim.
+static LAST_HANDLE: AtomicU64 = AtomicU64::new(0);
 
-fn insert_handle(p: PortState) -> u64 {
+fn next_generation() -> u32 {
+    loop {
+        let g = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        if g != 0 {
+            return g;
+        }
+    }
+}
+
+fn split_handle(h: u64) -> (u32, usize) {
+    ((h >> 32) as u32, (h & 0xffff_ffff) as usize)
+}
+
+fn insert_handle(p: PortState) -> Result<u64, SerialError> {
     let mut slab = HANDLES.lock().unwrap();
-    let key = slab.insert(p);
-    key as u64
+    // Checked again here, under the same lock as the insert, in case another
+    // thread opened the device since the caller's check.
+    check_exclusive(&slab, &p.device, p.exclusive)?;
+    let generation = next_generation();
+    let key = slab.insert(Entry {
+        generation,
+        state: Arc::new(p),
+    });
+    Ok((u64::from(generation) << 32) | key as u64)
+}
+
+// atexit handler (see restore::restore_at_exit). Skips the ports if another
+// thread holds the table at exit rather than risk hanging the process.
+#[cfg(target_os = "linux")]
+extern "C" fn restore_all() {
+    if let Ok(slab) = HANDLES.try_lock() {
+        for (_, e) in slab.iter() {
+            e.state.restore();
+        }
+    }
+}
+
+// Identifies the device behind `path`, so aliases such as the symlinks under
+// /dev/serial/by-id count as the same port.
+#[cfg(unix)]
+fn device_key(path: &str) -> String {
+    use std::os::unix::fs::MetadataExt;
+    match std::fs::metadata(path) {
+        Ok(m) => format!("rdev:{}", m.rdev()),
+        Err(_) => path.to_owned(),
+    }
+}
+
+#[cfg(windows)]
+fn device_key(path: &str) -> String {
+    path.trim_start_matches(r"\\.\").to_ascii_uppercase()
+}
+
+// An exclusive open fails while any other handle has the device, and no
+// handle may share a device opened exclusively.
+fn check_exclusive(slab: &Slab<Entry>, device: &str, exclusive: bool) -> Result<(), SerialError> {
+    let clash = slab
+        .iter()
+        .any(|(_, e)| e.state.device == device && (exclusive || e.state.exclusive));
+    if clash {
+        return Err(SerialError::new(
+            ErrorKind::Busy,
+            "port is already open in this process",
+        ));
+    }
+    Ok(())
 }
 
-fn with_state_mut<F, R>(h: u64, f: F) -> Result<R, c_int>
+// Resolve `h` to its slab index, telling apart a handle that was closed (and
+// whose slot may now belong to another port) from one that was never issued.
+fn check_handle(slab: &Slab<Entry>, h: u64) -> Result<usize, SerialError> {
+    let (generation, idx) = split_handle(h);
+    match slab.get(idx) {
+        Some(e) if generation != 0 && e.generation == generation => Ok(idx),
+        _ if generation != 0 && generation < NEXT_GENERATION.load(Ordering::Relaxed) => {
+            Err(SerialError::new(ErrorKind::StaleHandle, "stale handle"))
+        }
+        _ => Err(SerialError::new(ErrorKind::InvalidHandle, "invalid handle")),
+    }
+}
+
+fn get_state(h: u64, operation: &'static str) -> Result<Arc<PortState>, c_int> {
+    let slab = HANDLES.lock().unwrap();
+    match check_handle(&slab, h) {
+        Ok(idx) => Ok(Arc::clone(&slab[idx].state)),
+        Err(e) => Err(set_err(operation, None, e)),
+    }
+}
+
+fn with_state<F, R>(h: u64, half: Half, operation: &'static str, f: F) -> Result<R, c_int>
 where
-    F: FnOnce(&mut PortState) -> Result<R, c_int>,
+    F: FnOnce(&PortState) -> Result<R, SerialError>,
 {
-    let mut slab = HANDLES.lock().unwrap();
-    let idx = h as usize;
-    if let Some(state) = slab.get_mut(idx) {
-        f(state)
-    } else {
-        Err(set_err("invalid handle"))
-    }
+    let slab = HANDLES.lock().unwrap();
+    let state = match check_handle(&slab, h) {
+        Ok(idx) => Arc::clone(&slab[idx].state),
+        Err(e) => return Err(set_err(operation, None, e)),
+    };
+    // Begun under the table lock, so serial_close either finds this call in
+    // flight and wakes it, or runs first and the lookup above fails.
+    let _in_flight = state.waker(half).map(sys::Waker::begin);
+    drop(slab);
+    f(&state).map_err(|e| {
+        let record = ErrorRecord::new(operation, Some(&state.path), e);
+        *state.last_error(half).lock().unwrap() = Some(record.clone());
+        error::set_last(record)
+    })
 }
 
+fn with_port<F, R>(h: u64, half: Half, operation: &'static str, f: F) -> Result<R, c_int>
+where
+    F: FnOnce(&mut Port) -> Result<R, SerialError>,
+{
+    with_state(h, half, operation, |state| match half {
+        Half::Read => f(&mut state.reader.lock().unwrap().port),
+        Half::Write => f(&mut state.writer.lock().unwrap()),
+        Half::Control | Half::Event => f(&mut state.control.lock().unwrap()),
+    })
+}
+
+// Legacy positional form of serial_open_ex: out-of-range values fall back to
+// defaults. On Linux `xon`/`xoff`/`xany` set IXON/IXOFF/IXANY individually;
+// elsewhere either of `xon`/`xoff` means software flow control and `xany` is
+// ignored. The original entry point: the handle is only available through
+// `serial_last_handle`. Use serial_open2 or serial_open_ex instead.
 #[no_mangle]
 pub extern "C" fn serial_open(
     path: *const c_char,
@@ -77,15 +448,51 @@ pub extern "C" fn serial_open(
     rtscts: u8,
     xon: u8,
     xoff: u8,
-    _xany: u8,
+    xany: u8,
     read_timeout_ms: i32,
 ) -> c_int {
+    serial_open2(
+        path,
+        baud,
+        data_bits,
+        parity,
+        stop_bits,
+        rtscts,
+        xon,
+        xoff,
+        xany,
+        read_timeout_ms,
+        std::ptr::null_mut(),
+    )
+}
+
+// serial_open, also writing the new handle to `out_handle` (may be null).
+#[no_mangle]
+pub extern "C" fn serial_open2(
+    path: *const c_char,
+    baud: u32,
+    data_bits: u8,
+    parity: u8,
+    stop_bits: u8,
+    rtscts: u8,
+    xon: u8,
+    xoff: u8,
+    xany: u8,
+    read_timeout_ms: i32,
+    out_handle: *mut u64,
+) -> c_int {
+    if path.is_null() {
+        return invalid_arg("open", "null path");
+    }
     let path = unsafe { CStr::from_ptr(path) };
     let path = match path.to_str() {
         Ok(s) => s,
-        Err(e) => return set_err(e),
+        Err(e) => return invalid_arg("open", &e.to_string()),
     };
 
+    if let Err(e) = check_exclusive(&HANDLES.lock().unwrap(), &device_key(path), false) {
+        return set_err("open", Some(path), e);
+    }
     let mut builder = serialport::new(path, baud);
     builder = builder
         .data_bits(match data_bits {
@@ -111,54 +518,208 @@ pub extern "C" fn serial_open(
             serialport::FlowControl::None
         });
 
-    // Many implementations prefer a finite read timeout. When -1 is passed,
-    // set a reasonably large timeout instead of infinite.
-    if read_timeout_ms >= 0 {
-        builder = builder.timeout(Duration::from_millis(read_timeout_ms as u64));
+    // Reads honour -1 as "wait forever"; the builder timeout only bounds
+    // writes, which keep a finite limit.
+    let read_timeout =
+        (read_timeout_ms >= 0).then(|| Duration::from_millis(read_timeout_ms as u64));
+    builder = builder.timeout(read_timeout.unwrap_or(Duration::from_millis(10_000)));
+
+    match builder.open_native() {
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        Ok(mut port) => {
+            #[cfg(target_os = "linux")]
+            if let Err(e) = config::ConfigUpdate::software_flow(xon != 0, xoff != 0, xany != 0)
+                .apply(&mut port, |_| {})
+            {
+                return set_err("open", Some(path), e);
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = xany;
+            let setup = PortSetup {
+                baud,
+                read_timeout,
+                read_policy: Default::default(),
+                exclusive: false,
+                #[cfg(unix)]
+                lock_file: None,
+                #[cfg(target_os = "linux")]
+                saved: None,
+            };
+            register_port("open", path, port, setup, out_handle)
+        }
+        Err(e) => set_err("open", Some(path), e),
+    }
+}
+
+// Open a port from a JSON object: {version: 1, path, baudRate, dataBits?,
+// parity?, stopBits?, flowControl?, readTimeoutMs?, exclusive?, lockFile?,
+// lockDir?, dtr?, rts?, ...} (keys and values as in
+// serial_get_config). Unknown keys and invalid values fail with
+// ERR_INVALID_CONFIG before the device is touched.
+#[no_mangle]
+pub extern "C" fn serial_open_ex(json: *const c_char, out_handle: *mut u64) -> c_int {
+    if json.is_null() {
+        return invalid_arg("open", "null options");
+    }
+    if out_handle.is_null() {
+        return invalid_arg("open", "null handle");
+    }
+    let opts = match unsafe { CStr::from_ptr(json) }.to_str() {
+        Ok(s) => config::OpenOptions::parse(s),
+        Err(e) => return invalid_arg("open", &e.to_string()),
+    };
+    let opts = match opts {
+        Ok(o) => o,
+        Err(e) => return set_err("open", None, e),
+    };
+    let path = opts.path.as_str();
+    let read_timeout = opts.read_timeout();
+    // Before opening: serialport reconfigures the device on open, which would
+    // disturb the handle that already owns it.
+    if let Err(e) = check_exclusive(&HANDLES.lock().unwrap(), &device_key(path), opts.exclusive) {
+        return set_err("open", Some(path), e);
+    }
+    // Taken before the device is opened, like other UUCP-lock users do; it is
+    // released (dropped) on any failure below.
+    #[cfg(unix)]
+    let lock_file = if opts.lock_file {
+        let dir = opts.lock_dir.as_deref().unwrap_or(lockfile::DEFAULT_DIR);
+        match lockfile::LockFile::acquire(std::path::Path::new(dir), path) {
+            Ok(l) => Some(l),
+            Err(e) => return set_err("open", Some(path), e),
+        }
+    } else {
+        None
+    };
+    // On Linux the builder rate is a placeholder (serialport rejects
+    // non-standard rates on some targets); the requested one is applied with
+    // the other line settings below.
+    #[cfg(target_os = "linux")]
+    let open_baud = 9600;
+    #[cfg(not(target_os = "linux"))]
+    let open_baud = opts.baud_rate;
+    // Before serialport makes the port raw.
+    #[cfg(target_os = "linux")]
+    let (snapshot_fd, saved) = if opts.restore_on_close {
+        match restore::take(path) {
+            Ok((fd, saved)) => (Some(fd), Some(saved)),
+            Err(e) => return set_err("open", Some(path), e),
+        }
     } else {
-        builder = builder.timeout(Duration::from_millis(10_000));
+        (None, None)
+    };
+    let builder = serialport::new(path, open_baud)
+        .timeout(read_timeout.unwrap_or(Duration::from_millis(10_000)));
+    let mut port = match builder.open_native() {
+        Ok(p) => p,
+        Err(e) => return set_err("open", Some(path), e),
+    };
+    #[cfg(target_os = "linux")]
+    drop(snapshot_fd);
+    let configured = (|| {
+        // COM ports are always exclusive on Windows.
+        #[cfg(unix)]
+        if opts.exclusive {
+            sys::lock_exclusive(&port)?;
+        } else {
+            // serialport sets TIOCEXCL on every open.
+            port.set_exclusive(false)?;
+        }
+        opts.line_settings().apply(&mut port, |_| {})?;
+        set_open_lines(&mut port, opts.dtr, opts.rts)
+    })();
+    if let Err(e) = configured {
+        #[cfg(target_os = "linux")]
+        if let Some(saved) = &saved {
+            let _ = saved.restore(std::os::unix::io::AsRawFd::as_raw_fd(&port));
+        }
+        return set_err("open", Some(path), e);
+    }
+    #[cfg(target_os = "linux")]
+    if saved.is_some() {
+        restore::restore_at_exit();
+    }
+    let setup = PortSetup {
+        baud: opts.baud_rate,
+        read_timeout,
+        read_policy: opts.line_settings().read_policy(Default::default()),
+        exclusive: opts.exclusive,
+        #[cfg(unix)]
+        lock_file,
+        #[cfg(target_os = "linux")]
+        saved,
+    };
+    register_port("open", path, port, setup, out_handle)
+}
+
+// Initial DTR/RTS levels, set before the handle is handed out.
+fn set_open_lines(
+    port: &mut Port,
+    dtr: Option<bool>,
+    rts: Option<bool>,
+) -> Result<(), SerialError> {
+    if let Some(dtr) = dtr {
+        port.write_data_terminal_ready(dtr)?;
     }
+    if let Some(rts) = rts {
+        port.write_request_to_send(rts)?;
+    }
+    Ok(())
+}
 
-    match builder.open() {
-        Ok(port) => {
-            let h = insert_handle(PortState { port });
-            unsafe {
-                LAST_HANDLE = h;
-            }
-            0
+// Wrap a freshly opened port in a handle and store it in `out_handle`.
+fn register_port(
+    operation: &'static str,
+    path: &str,
+    port: Port,
+    setup: PortSetup,
+    out_handle: *mut u64,
+) -> c_int {
+    let h = match PortState::new(path, port, setup).and_then(insert_handle) {
+        Ok(h) => h,
+        Err(e) => return set_err(operation, Some(path), e),
+    };
+    LAST_HANDLE.store(h, Ordering::Relaxed);
+    if !out_handle.is_null() {
+        unsafe {
+            *out_handle = h;
         }
-        Err(e) => set_err(e),
     }
+    0
 }
 
+// Deprecated: racy when several threads open ports concurrently. Use the
+// `out_handle` parameter of `serial_open2` or `serial_open_ex` instead.
 #[no_mangle]
 pub extern "C" fn serial_last_handle() -> u64 {
-    unsafe { LAST_HANDLE }
+    LAST_HANDLE.load(Ordering::Relaxed)
 }
 
 #[no_mangle]
 pub extern "C" fn serial_close(h: u64) -> c_int {
     let mut slab = HANDLES.lock().unwrap();
-    let idx = h as usize;
-    if slab.contains(idx) {
-        slab.remove(idx);
-        0
-    } else {
-        set_err("invalid handle")
+    match check_handle(&slab, h) {
+        Ok(idx) => {
+            // The port itself is dropped once any in-flight call releases it;
+            // wake blocked calls so that happens now.
+            #[cfg(target_os = "linux")]
+            reactor::deregister(h);
+            slab.remove(idx).state.wake(CANCEL_ALL);
+            0
+        }
+        Err(e) => set_err("close", None, e),
     }
 }
 
 #[no_mangle]
 pub extern "C" fn serial_write(h: u64, buf: *const u8, len: usize) -> isize {
     if buf.is_null() {
-        return set_err("null buffer") as isize;
+        return invalid_arg("write", "null buffer") as isize;
     }
-    let res = with_state_mut(h, |state| {
+    let res = with_state(h, Half::Write, "write", |state| {
         let data = unsafe { std::slice::from_raw_parts(buf, len) };
-        match state.port.write(data) {
-            Ok(n) => Ok(n as isize),
-            Err(e) => Err(set_err(e)),
-        }
+        let mut port = state.writer.lock().unwrap();
+        Ok(sys::write(&mut port, data, &state.write_waker)? as isize)
     });
     match res {
         Ok(n) => n,
@@ -166,30 +727,203 @@ pub extern "C" fn serial_write(h: u64, buf: *const u8, len: usize) -> isize {
     }
 }
 
-// If timeout_ms >= 0, update the read timeout for this call.
+// If timeout_ms >= 0, update the port's read timeout (kept for later calls);
+// a port opened with -1 blocks until data arrives or it is closed. The other
+// read calls take `timeout_ms` for that call only and leave it unchanged.
+// Returns >0 bytes read, 0 if the timeout expired without data,
+// ERR_DISCONNECTED at end of stream / device gone, ERR_CANCELLED if the read
+// was interrupted, or another negative error code.
+// With a receive buffer (serial_set_rx_buffer) it never blocks: it returns
+// buffered bytes or ERR_WOULD_BLOCK, and reports end of stream / device errors
+// once the buffer is empty.
+// With a data callback instead, the callback gets the data and this (like
+// the other read variants) fails with ERR_BUSY.
 #[no_mangle]
 pub extern "C" fn serial_read(h: u64, buf: *mut u8, len: usize, timeout_ms: i32) -> isize {
     if buf.is_null() {
-        return set_err("null buffer") as isize;
+        return invalid_arg("read", "null buffer") as isize;
     }
-    let res = with_state_mut(h, |state| {
+    let res = with_state(h, Half::Read, "read", |state| {
+        not_subscribed(state)?;
+        let out = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        let n = state.read_ahead.lock().unwrap().pop(out);
+        if n > 0 {
+            return Ok(n as isize);
+        }
+        if let Some(ring) = state.rx.lock().unwrap().as_mut() {
+            return match ring.pop(out) {
+                0 if len > 0 => Err(ring.error().cloned().unwrap_or_else(|| {
+                    SerialError::new(ErrorKind::WouldBlock, "no data buffered")
+                })),
+                n => Ok(n as isize),
+            };
+        }
+        direct_reads_only(state)?;
+        let mut reader = state.reader.lock().unwrap();
         if timeout_ms >= 0 {
-            let _ = state
-                .port
-                .set_timeout(Duration::from_millis(timeout_ms as u64));
+            reader.timeout = Some(Duration::from_millis(timeout_ms as u64));
+        }
+        if len == 0 {
+            return Ok(0);
         }
+        let timeout = reader.timeout;
+        let policy = *state.read_policy.lock().unwrap();
+        read_port(state, &mut reader.port, out, timeout, policy)
+    });
+    match res {
+        Ok(n) => n,
+        Err(code) => code as isize,
+    }
+}
+
+// A direct read: wait for the first bytes, then keep reading as `policy` asks
+// until `timeout` (counted from the start) runs out.
+fn read_port(
+    state: &PortState,
+    port: &mut Port,
+    out: &mut [u8],
+    timeout: Option<Duration>,
+    policy: config::ReadPolicy,
+) -> Result<isize, SerialError> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let n = read_first(port, out, &state.read_waker, timeout)?;
+    if n == 0 {
+        return Ok(0);
+    }
+    Ok(read_more(state, port, out, n, deadline, policy)? as isize)
+}
+
+// Wait up to `timeout` for data; 0 if none came.
+fn read_first(
+    port: &mut Port,
+    out: &mut [u8],
+    waker: &sys::Waker,
+    timeout: Option<Duration>,
+) -> Result<usize, SerialError> {
+    match sys::read(port, out, waker, timeout) {
+        // The fd polled readable but yielded nothing: the other end is gone.
+        Ok(0) => Err(SerialError::new(ErrorKind::Disconnected, "end of stream")),
+        Ok(n) => Ok(n),
+        // Treat timeout as 0 bytes so JS can easily retry.
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Add to the `n` bytes already in `out` as `policy` asks, until `deadline`.
+// Returns the new total. If the call is cancelled meanwhile, it fails with
+// ERR_CANCELLED and the bytes go back to the read-ahead buffer.
+fn read_more(
+    state: &PortState,
+    port: &mut Port,
+    out: &mut [u8],
+    mut n: usize,
+    deadline: Option<Instant>,
+    policy: config::ReadPolicy,
+) -> Result<usize, SerialError> {
+    let want = policy.min_bytes.min(out.len());
+    while n < out.len() {
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let wait = match policy.inter_byte_timeout {
+            _ if n < want => remaining,
+            Some(gap) => Some(remaining.map_or(gap, |r| r.min(gap))),
+            None => break,
+        };
+        if wait == Some(Duration::ZERO) {
+            break;
+        }
+        // Otherwise bytes already read are returned; an error or end of
+        // stream shows up on the following read.
+        match sys::read(port, &mut out[n..], &state.read_waker, wait) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                state.read_ahead.lock().unwrap().unread(&out[..n]);
+                return Err(e.into());
+            }
+            Err(_) => break,
+        }
+    }
+    Ok(n)
+}
+
+// Read one frame: everything up to a silence of the longer of `gap_us` and
+// `gap_chars` character times at the port's current settings (Modbus RTU:
+// 3.5 characters, at least 1750 us). `timeout_ms` bounds the wait for the
+// first byte (-1: the handle's read timeout); the frame then ends only at the
+// gap or when `buf` is full, in which case the rest comes with the next call.
+// The monotonic time (see monotonic_ns) at which the first bytes were read is
+// stored in `out_first_ns` (may be null); 0 when the frame starts with bytes
+// from the read-ahead buffer, whose arrival time is not kept. Returns the
+// frame length, 0 if no frame started in time, or a negative error code. Not
+// available with a receive buffer.
+#[no_mangle]
+pub extern "C" fn serial_read_frame(
+    h: u64,
+    buf: *mut u8,
+    len: usize,
+    gap_us: u32,
+    gap_chars: f64,
+    timeout_ms: i32,
+    out_first_ns: *mut u64,
+) -> isize {
+    if buf.is_null() || len == 0 {
+        return invalid_arg("read_frame", "empty buffer") as isize;
+    }
+    let res = with_state(h, Half::Read, "read_frame", |state| {
+        if !(gap_chars.is_finite() && gap_chars >= 0.0) {
+            return Err(SerialError::new(
+                ErrorKind::InvalidConfig,
+                format!("invalid gap_chars {gap_chars}"),
+            ));
+        }
+        direct_reads_only(state)?;
+        let char_time = if gap_chars > 0.0 {
+            let requested = state.requested_baud.load(Ordering::Relaxed);
+            let policy = *state.read_policy.lock().unwrap();
+            config::Config::read(&state.control.lock().unwrap(), requested, policy)?.char_time()
+        } else {
+            Duration::ZERO
+        };
+        let char_gap =
+            Duration::try_from_secs_f64(char_time.as_secs_f64() * gap_chars).map_err(|_| {
+                SerialError::new(
+                    ErrorKind::InvalidConfig,
+                    format!("gap_chars {gap_chars} is too large"),
+                )
+            })?;
+        let gap = Duration::from_micros(gap_us.into()).max(char_gap);
+        if gap.is_zero() {
+            return Err(SerialError::new(
+                ErrorKind::InvalidConfig,
+                "gap must be positive",
+            ));
+        }
+
+        let mut reader = state.reader.lock().unwrap();
+        let timeout = read_timeout(&reader, timeout_ms);
         let out = unsafe { std::slice::from_raw_parts_mut(buf, len) };
-        match state.port.read(out) {
-            Ok(n) => Ok(n as isize),
-            Err(e) => {
-                // Treat timeout as 0 bytes so JS can easily retry.
-                if e.kind() == std::io::ErrorKind::TimedOut {
-                    Ok(0)
-                } else {
-                    Err(set_err(e))
-                }
+        // Read-ahead bytes start the frame; when they arrived is not known.
+        let mut n = state.read_ahead.lock().unwrap().pop(out);
+        let mut first_ns = 0;
+        if n == 0 {
+            n = read_first(&mut reader.port, out, &state.read_waker, timeout)?;
+            first_ns = monotonic_ns();
+        }
+        if n == 0 {
+            return Ok(0);
+        }
+        let policy = config::ReadPolicy {
+            min_bytes: 1,
+            inter_byte_timeout: Some(gap),
+        };
+        let n = read_more(state, &mut reader.port, out, n, None, policy)?;
+        if !out_first_ns.is_null() {
+            unsafe {
+                *out_first_ns = first_ns;
             }
         }
+        Ok(n as isize)
     });
     match res {
         Ok(n) => n,
@@ -197,28 +931,513 @@ pub extern "C" fn serial_read(h: u64, buf: *mut u8, len: usize, timeout_ms: i32)
     }
 }
 
+// Like serial_read, also storing in `out_ns` when the returned bytes arrived
+// (see monotonic_ns): taken right after the kernel read that returned the
+// first of them, by the reactor thread with a receive buffer. Bytes from the
+// read-ahead buffer have no known arrival time and report 0. Later reads may
+// be coalesced into the same chunk as with serial_read; with `split` != 0
+// the chunk ends with the first read, so the time is accurate to its first
+// byte. Unlike serial_read, `timeout_ms` >= 0 applies to this call only and
+// the handle's read timeout (used for -1) is left as it is.
+#[no_mangle]
+pub extern "C" fn serial_read_ts(
+    h: u64,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: i32,
+    split: c_int,
+    out_ns: *mut u64,
+) -> isize {
+    if buf.is_null() || len == 0 {
+        return invalid_arg("read_ts", "empty buffer") as isize;
+    }
+    let res = with_state(h, Half::Read, "read_ts", |state| {
+        let out = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        let (n, ns) = read_stamped(state, out, timeout_ms, split != 0)?;
+        if !out_ns.is_null() {
+            unsafe {
+                *out_ns = ns;
+            }
+        }
+        Ok(n as isize)
+    });
+    match res {
+        Ok(n) => n,
+        Err(code) => code as isize,
+    }
+}
+
+fn read_stamped(
+    state: &PortState,
+    out: &mut [u8],
+    timeout_ms: i32,
+    split: bool,
+) -> Result<(usize, u64), SerialError> {
+    not_subscribed(state)?;
+    let n = state.read_ahead.lock().unwrap().pop(out);
+    if n > 0 {
+        return Ok((n, 0));
+    }
+    if let Some(ring) = state.rx.lock().unwrap().as_mut() {
+        return match ring.pop_stamped(out, split) {
+            (0, _) => Err(ring
+                .error()
+                .cloned()
+                .unwrap_or_else(|| SerialError::new(ErrorKind::WouldBlock, "no data buffered"))),
+            chunk => Ok(chunk),
+        };
+    }
+    direct_reads_only(state)?;
+    let mut reader = state.reader.lock().unwrap();
+    let timeout = read_timeout(&reader, timeout_ms);
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let n = read_first(&mut reader.port, out, &state.read_waker, timeout)?;
+    let ns = monotonic_ns();
+    if n == 0 {
+        return Ok((0, 0));
+    }
+    if split {
+        return Ok((n, ns));
+    }
+    let policy = *state.read_policy.lock().unwrap();
+    let n = read_more(state, &mut reader.port, out, n, deadline, policy)?;
+    Ok((n, ns))
+}
+
+// Read exactly `len` bytes. `timeout_ms` (-1: the handle's read timeout) is
+// one deadline for the whole call; if it passes first, the call fails with
+// ERR_TIMED_OUT and the bytes read so far wait for the next read. Not
+// available with a receive buffer.
+#[no_mangle]
+pub extern "C" fn serial_read_exact(h: u64, buf: *mut u8, len: usize, timeout_ms: i32) -> isize {
+    if buf.is_null() && len > 0 {
+        return invalid_arg("read_exact", "null buffer") as isize;
+    }
+    let res = with_state(h, Half::Read, "read_exact", |state| {
+        direct_reads_only(state)?;
+        if len == 0 {
+            return Ok(0);
+        }
+        let out = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        let mut reader = state.reader.lock().unwrap();
+        let deadline = read_deadline(&reader, timeout_ms);
+        let n = read_until_done(state, &mut reader, out, deadline, |data| {
+            (data.len() == len).then_some(len)
+        })?;
+        Ok(n as isize)
+    });
+    match res {
+        Ok(n) => n,
+        Err(code) => code as isize,
+    }
+}
+
+// Read up to and including the first occurrence of `delim` (e.g. "\r\n").
+// Bytes after it wait for the next read. If `buf` fills first, its `len`
+// bytes are returned without the delimiter. Deadline and failures as in
+// serial_read_exact.
+#[no_mangle]
+pub extern "C" fn serial_read_until(
+    h: u64,
+    delim: *const u8,
+    delim_len: usize,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: i32,
+) -> isize {
+    if delim.is_null() || delim_len == 0 {
+        return invalid_arg("read_until", "empty delimiter") as isize;
+    }
+    if buf.is_null() || len == 0 {
+        return invalid_arg("read_until", "empty buffer") as isize;
+    }
+    let delim = unsafe { std::slice::from_raw_parts(delim, delim_len) };
+    let res = with_state(h, Half::Read, "read_until", |state| {
+        direct_reads_only(state)?;
+        let out = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        let mut reader = state.reader.lock().unwrap();
+        let deadline = read_deadline(&reader, timeout_ms);
+        let mut search = DelimiterSearch::new(delim);
+        let n = read_until_done(state, &mut reader, out, deadline, |data| {
+            search.find(data).or((data.len() == len).then_some(len))
+        })?;
+        Ok(n as isize)
+    });
+    match res {
+        Ok(n) => n,
+        Err(code) => code as isize,
+    }
+}
+
+// Finds a delimiter in a buffer that grows between calls, without rescanning
+// what was searched before; a match may straddle two reads.
+struct DelimiterSearch<'a> {
+    delim: &'a [u8],
+    // Where the next search starts.
+    from: usize,
+}
+
+impl<'a> DelimiterSearch<'a> {
+    fn new(delim: &'a [u8]) -> Self {
+        DelimiterSearch { delim, from: 0 }
+    }
+
+    // Length of `data` up to and including the first delimiter, if any.
+    fn find(&mut self, data: &[u8]) -> Option<usize> {
+        let found = data[self.from..]
+            .windows(self.delim.len())
+            .position(|w| w == self.delim)
+            .map(|i| self.from + i + self.delim.len());
+        self.from = data.len().saturating_sub(self.delim.len() - 1);
+        found
+    }
+}
+
+// Reads that go to the device itself would race the reactor, which drains it
+// while a receive buffer or a data callback is set.
+fn direct_reads_only(state: &PortState) -> Result<(), SerialError> {
+    not_subscribed(state)?;
+    if state.rx.lock().unwrap().is_some() {
+        return Err(SerialError::new(
+            ErrorKind::Unsupported,
+            "not available while the receive buffer is on",
+        ));
+    }
+    Ok(())
+}
+
+// A data callback takes everything received, including what the receive
+// buffer held when it was set, so no read may compete with it.
+fn not_subscribed(state: &PortState) -> Result<(), SerialError> {
+    #[cfg(target_os = "linux")]
+    if state.callback.lock().unwrap().is_some() {
+        return Err(SerialError::new(
+            ErrorKind::Busy,
+            "received data goes to the data callback",
+        ));
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
+    Ok(())
+}
+
+// The timeout of one read call: `timeout_ms` if >= 0, else the handle's.
+fn read_timeout(reader: &Reader, timeout_ms: i32) -> Option<Duration> {
+    if timeout_ms >= 0 {
+        Some(Duration::from_millis(timeout_ms as u64))
+    } else {
+        reader.timeout
+    }
+}
+
+fn read_deadline(reader: &Reader, timeout_ms: i32) -> Option<Instant> {
+    read_timeout(reader, timeout_ms).map(|t| Instant::now() + t)
+}
+
+// Fill `out` (read-ahead bytes first) until `done` returns how many bytes to
+// hand back; the rest go back to the read-ahead buffer. On failure,
+// including the deadline passing, everything read goes back instead.
+fn read_until_done(
+    state: &PortState,
+    reader: &mut Reader,
+    out: &mut [u8],
+    deadline: Option<Instant>,
+    mut done: impl FnMut(&[u8]) -> Option<usize>,
+) -> Result<usize, SerialError> {
+    let waker = &state.read_waker;
+    let mut n = state.read_ahead.lock().unwrap().pop(out);
+    loop {
+        if let Some(end) = done(&out[..n]) {
+            state.read_ahead.lock().unwrap().unread(&out[end..n]);
+            return Ok(end);
+        }
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let res = match remaining {
+            Some(Duration::ZERO) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "deadline passed",
+            )),
+            _ => sys::read(&mut reader.port, &mut out[n..], waker, remaining),
+        };
+        let err = match res {
+            Ok(0) => SerialError::new(ErrorKind::Disconnected, "end of stream"),
+            Ok(k) => {
+                n += k;
+                continue;
+            }
+            Err(e) => e.into(),
+        };
+        state.read_ahead.lock().unwrap().unread(&out[..n]);
+        return Err(err);
+    }
+}
+
+// Copy up to `len` of the next bytes into `buf` without consuming them;
+// returns how many. Bytes that have already arrived are moved into the
+// read-ahead buffer first, without waiting. While another read is in flight
+// on the handle, only what the read-ahead buffer already holds is seen.
+#[no_mangle]
+pub extern "C" fn serial_peek(h: u64, buf: *mut u8, len: usize) -> isize {
+    if buf.is_null() {
+        return invalid_arg("peek", "null buffer") as isize;
+    }
+    let res = with_state(h, Half::Read, "peek", |state| {
+        not_subscribed(state)?;
+        let out = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        // Held throughout, so a concurrent read cannot take newer bytes
+        // before these.
+        let mut ahead = state.read_ahead.lock().unwrap();
+        let want = len.saturating_sub(ahead.len());
+        if want > 0 {
+            let mut fresh = vec![0u8; want];
+            let n = read_available(state, &mut fresh)?;
+            ahead.push(&fresh[..n]);
+        }
+        Ok(ahead.peek(out) as isize)
+    });
+    match res {
+        Ok(n) => n,
+        Err(code) => code as isize,
+    }
+}
+
+// Whatever the receive buffer or the device has right now, without waiting.
+fn read_available(state: &PortState, out: &mut [u8]) -> Result<usize, SerialError> {
+    if let Some(ring) = state.rx.lock().unwrap().as_mut() {
+        return Ok(ring.pop(out));
+    }
+    direct_reads_only(state)?;
+    let Ok(mut reader) = state.reader.try_lock() else {
+        return Ok(0);
+    };
+    match sys::read(
+        &mut reader.port,
+        out,
+        &state.read_waker,
+        Some(Duration::ZERO),
+    ) {
+        // 0 is end of stream, which the next read reports.
+        Ok(n) => Ok(n),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Drop up to `n` bytes from the read-ahead buffer (typically ones just seen
+// with serial_peek); returns how many were dropped.
+#[no_mangle]
+pub extern "C" fn serial_consume(h: u64, n: usize) -> isize {
+    with_state(h, Half::Read, "consume", |state| {
+        Ok(state.read_ahead.lock().unwrap().consume(n) as isize)
+    })
+    .unwrap_or_else(|code| code as isize)
+}
+
+// Push `len` bytes back so the next read returns them first.
+#[no_mangle]
+pub extern "C" fn serial_unread(h: u64, buf: *const u8, len: usize) -> c_int {
+    if buf.is_null() {
+        return invalid_arg("unread", "null buffer");
+    }
+    with_state(h, Half::Read, "unread", |state| {
+        let bytes = unsafe { std::slice::from_raw_parts(buf, len) };
+        state.read_ahead.lock().unwrap().unread(bytes);
+        Ok(0)
+    })
+    .unwrap_or_else(|code| code)
+}
+
+// Timestamps handed to JS, in nanoseconds: CLOCK_MONOTONIC on Unix (so they
+// compare across processes); on Windows, counted from the first call.
+fn monotonic_ns() -> u64 {
+    #[cfg(unix)]
+    {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+        }
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+    #[cfg(windows)]
+    {
+        static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+        EPOCH.elapsed().as_nanos() as u64
+    }
+}
+
+// Wake an in-flight read (which & 1), write (which & 2) and/or
+// serial_wait_event (which & 4) on `h`; the woken call returns ERR_CANCELLED,
+// a read leaving whatever it had already received for the next read. Calls
+// that start afterwards are not affected.
+#[no_mangle]
+pub extern "C" fn serial_cancel(h: u64, which: c_int) -> c_int {
+    if which & !CANCEL_ALL != 0 || which == 0 {
+        return invalid_arg(
+            "cancel",
+            "which must combine 1 (read), 2 (write), 4 (event)",
+        );
+    }
+    match get_state(h, "cancel") {
+        Ok(state) => {
+            state.wake(which);
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+// Run the port in buffered mode: a shared background thread drains it into a
+// ring of `capacity` bytes, dropping the oldest bytes when full. A later call
+// resizes the ring; capacity 0 stops buffering and discards what is left.
+// Capacities above ring::MAX_CAPACITY (64 MiB) are ERR_INVALID_CONFIG.
+// Linux only (ERR_UNSUPPORTED elsewhere).
+#[no_mangle]
+pub extern "C" fn serial_set_rx_buffer(h: u64, capacity: usize) -> c_int {
+    // Hold the table lock so a concurrent close cannot slip in between the
+    // handle check and the reactor registration.
+    let slab = HANDLES.lock().unwrap();
+    let state = match check_handle(&slab, h) {
+        Ok(idx) => Arc::clone(&slab[idx].state),
+        Err(e) => return set_err("set_rx_buffer", None, e),
+    };
+    if capacity > ring::MAX_CAPACITY {
+        return set_err(
+            "set_rx_buffer",
+            Some(&state.path),
+            SerialError::new(
+                ErrorKind::InvalidConfig,
+                format!(
+                    "capacity {capacity} exceeds the maximum of {}",
+                    ring::MAX_CAPACITY
+                ),
+            ),
+        );
+    }
+    if capacity == 0 {
+        *state.rx.lock().unwrap() = None;
+        #[cfg(target_os = "linux")]
+        if state.callback.lock().unwrap().is_none() {
+            reactor::deregister(h);
+        }
+        return 0;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // Install the ring before registering so no drained byte is lost.
+        let mut rx = state.rx.lock().unwrap();
+        match rx.as_mut() {
+            Some(ring) => ring.resize(capacity),
+            None => *rx = Some(ring::RxRing::new(capacity)),
+        }
+        drop(rx);
+        if let Err(e) = reactor::register(h, &state) {
+            *state.rx.lock().unwrap() = None;
+            return set_err("set_rx_buffer", Some(&state.path), e);
+        }
+        0
+    }
+    #[cfg(not(target_os = "linux"))]
+    set_err(
+        "set_rx_buffer",
+        Some(&state.path),
+        SerialError::new(
+            ErrorKind::Unsupported,
+            "receive buffering is only available on Linux",
+        ),
+    )
+}
+
+// Deliver received data to `cb` from the library's background thread instead
+// of serial_read: cb(user_data, kind, value, data, len) with kind 0 = data
+// chunk (valid only during the call), 1 = modem-line / line-error events in
+// `value` (bits as in serial_wait_event), 2 = last call, `value` being 0 after
+// unregistering or closing, else the error code that ended the stream. A null
+// `cb` unregisters; replacing or unregistering does not wait for the final
+// call. All ports share the thread, so callbacks should return quickly.
+// Anything still in the receive buffer is delivered first, and while a
+// callback is set every read fails with ERR_BUSY.
+// Linux only (ERR_UNSUPPORTED elsewhere).
+#[no_mangle]
+pub extern "C" fn serial_set_data_callback(
+    h: u64,
+    cb: Option<extern "C" fn(*mut c_void, u32, i32, *const u8, usize)>,
+    user_data: *mut c_void,
+) -> c_int {
+    let slab = HANDLES.lock().unwrap();
+    let state = match check_handle(&slab, h) {
+        Ok(idx) => Arc::clone(&slab[idx].state),
+        Err(e) => return set_err("set_data_callback", None, e),
+    };
+    #[cfg(target_os = "linux")]
+    {
+        let cb = cb.map(|f| reactor::Callback::new(f, user_data));
+        let unregister = cb.is_none();
+        if let Err(e) = reactor::set_callback(h, &state, cb) {
+            return set_err("set_data_callback", Some(&state.path), e);
+        }
+        if unregister && state.rx.lock().unwrap().is_none() {
+            reactor::deregister(h);
+        }
+        0
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (cb, user_data);
+    #[cfg(not(target_os = "linux"))]
+    set_err(
+        "set_data_callback",
+        Some(&state.path),
+        SerialError::new(
+            ErrorKind::Unsupported,
+            "data callbacks are only available on Linux",
+        ),
+    )
+}
+
+// Bytes waiting in the receive buffer and bytes dropped because it was full
+// (both 0 when the port is not buffered). Either pointer may be null.
+#[no_mangle]
+pub extern "C" fn serial_rx_stats(
+    h: u64,
+    out_buffered: *mut usize,
+    out_overflow: *mut u64,
+) -> c_int {
+    match get_state(h, "rx_stats") {
+        Ok(state) => {
+            let rx = state.rx.lock().unwrap();
+            let (buffered, overflow) = rx.as_ref().map_or((0, 0), |r| (r.len(), r.overflow()));
+            unsafe {
+                if !out_buffered.is_null() {
+                    *out_buffered = buffered;
+                }
+                if !out_overflow.is_null() {
+                    *out_overflow = overflow;
+                }
+            }
+            0
+        }
+        Err(code) => code,
+    }
+}
+
 // rts/dtr/brk: -1=unchanged, 0=OFF, 1=ON
 #[no_mangle]
 pub extern "C" fn serial_set_lines(h: u64, rts: c_int, dtr: c_int, brk: c_int) -> c_int {
-    with_state_mut(h, |state| {
+    with_port(h, Half::Control, "set_lines", |port| {
         if rts >= 0 {
-            state
-                .port
-                .write_request_to_send(rts != 0)
-                .map_err(set_err)?;
+            port.write_request_to_send(rts != 0)?;
         }
         if dtr >= 0 {
-            state
-                .port
-                .write_data_terminal_ready(dtr != 0)
-                .map_err(set_err)?;
+            port.write_data_terminal_ready(dtr != 0)?;
         }
         if brk >= 0 {
             if brk != 0 {
                 // Some platforms may not support break set/clear
-                state.port.set_break().map_err(set_err)?;
+                port.set_break()?;
             } else {
-                state.port.clear_break().map_err(set_err)?;
+                port.clear_break()?;
             }
         }
         Ok(0)
@@ -230,26 +1449,26 @@ pub extern "C" fn serial_set_lines(h: u64, rts: c_int, dtr: c_int, brk: c_int) -
 #[no_mangle]
 pub extern "C" fn serial_get_lines(h: u64, out_mask: *mut u32) -> c_int {
     if out_mask.is_null() {
-        return set_err("null mask");
+        return invalid_arg("get_lines", "null mask");
     }
-    let res = with_state_mut(h, |state| {
+    let res = with_port(h, Half::Control, "get_lines", |port| {
         let mut mask: u32 = 0;
-        if let Ok(b) = state.port.read_clear_to_send() {
+        if let Ok(b) = port.read_clear_to_send() {
             if b {
                 mask |= 1 << 0;
             }
         }
-        if let Ok(b) = state.port.read_data_set_ready() {
+        if let Ok(b) = port.read_data_set_ready() {
             if b {
                 mask |= 1 << 1;
             }
         }
-        if let Ok(b) = state.port.read_carrier_detect() {
+        if let Ok(b) = port.read_carrier_detect() {
             if b {
                 mask |= 1 << 2;
             }
         }
-        if let Ok(b) = state.port.read_ring_indicator() {
+        if let Ok(b) = port.read_ring_indicator() {
             if b {
                 mask |= 1 << 3;
             }
@@ -262,51 +1481,316 @@ pub extern "C" fn serial_get_lines(h: u64, out_mask: *mut u32) -> c_int {
     res.unwrap_or_else(|code| code)
 }
 
+// Block until one of the events in `mask` fires (bits as in serial_get_lines,
+// plus 1<<4=BREAK, 1<<5=FRAME, 1<<6=PARITY, 1<<7=OVERRUN) and store the fired
+// subset in `out_events`. timeout_ms < 0 waits forever; on timeout returns 0
+// with `out_events` = 0. Cancellable with serial_cancel(h, 4). Fails with
+// ERR_UNSUPPORTED if the port cannot report one of the requested events.
+// Modem-line waits block in TIOCMIWAIT on Linux; see events.rs.
+#[no_mangle]
+pub extern "C" fn serial_wait_event(
+    h: u64,
+    mask: u32,
+    timeout_ms: i32,
+    out_events: *mut u32,
+) -> c_int {
+    if out_events.is_null() {
+        return invalid_arg("wait_event", "null events");
+    }
+    if mask == 0 || mask & !events::EV_ALL != 0 {
+        return invalid_arg("wait_event", "invalid event mask");
+    }
+    let timeout = (timeout_ms >= 0).then(|| Duration::from_millis(timeout_ms as u64));
+    with_state(h, Half::Event, "wait_event", |state| {
+        let snapshot = || events::Snapshot::take(&mut state.control.lock().unwrap());
+        let start = snapshot()?;
+        if mask & !start.supported() != 0 {
+            return Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "line error events are not supported on this port",
+            ));
+        }
+        let deadline = timeout.map(|t| Instant::now() + t);
+        #[cfg(target_os = "linux")]
+        if mask & events::EV_LINE_ERRORS == 0 {
+            let fd = std::os::unix::io::AsRawFd::as_raw_fd(&*state.control.lock().unwrap());
+            if let Some(fired) = wait_modem_change(state, fd, mask, &start, deadline, &snapshot)? {
+                unsafe {
+                    *out_events = fired;
+                }
+                return Ok(0);
+            }
+        }
+        let fired = loop {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let wait = remaining.map_or(events::POLL_INTERVAL, |r| r.min(events::POLL_INTERVAL));
+            if state.event_waker.wait(wait) {
+                return Err(SerialError::new(ErrorKind::Cancelled, "cancelled"));
+            }
+            let fired = snapshot()?.events_since(&start) & mask;
+            if fired != 0 || remaining.is_some_and(|r| r <= wait) {
+                break fired;
+            }
+        };
+        unsafe {
+            *out_events = fired;
+        }
+        Ok(0)
+    })
+    .unwrap_or_else(|code| code)
+}
+
+// serial_wait_event for modem lines through TIOCMIWAIT: the fired events (0
+// on timeout), or None if the driver or host does not allow it, leaving the
+// caller to sample instead.
+#[cfg(target_os = "linux")]
+fn wait_modem_change(
+    state: &PortState,
+    fd: std::os::unix::io::RawFd,
+    mask: u32,
+    start: &events::Snapshot,
+    deadline: Option<Instant>,
+    snapshot: &dyn Fn() -> Result<events::Snapshot, SerialError>,
+) -> Result<Option<u32>, SerialError> {
+    loop {
+        let Some(wait) = events::ModemWait::start(fd, mask)? else {
+            return Ok(None);
+        };
+        // Sample once shortly after starting, then block until the ioctl
+        // returns, the deadline passes or the waker fires.
+        let mut armed = false;
+        loop {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let slice = if armed {
+                remaining
+            } else {
+                Some(remaining.map_or(events::ARM_CHECK, |r| r.min(events::ARM_CHECK)))
+            };
+            match wait.wait(&state.event_waker, slice) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => {
+                    let _ = wait.finish();
+                    return Err(if e.kind() == std::io::ErrorKind::Interrupted {
+                        SerialError::new(ErrorKind::Cancelled, "cancelled")
+                    } else {
+                        e.into()
+                    });
+                }
+            }
+            let fired = snapshot()?.events_since(start) & mask;
+            if fired != 0 || deadline.is_some_and(|d| Instant::now() >= d) {
+                let _ = wait.finish();
+                return Ok(Some(fired));
+            }
+            armed = true;
+        }
+        match wait.finish() {
+            Ok(()) => {}
+            // No TIOCMIWAIT in this driver.
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOTTY | libc::EINVAL)) => {
+                return Ok(None)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+        let fired = snapshot()?.events_since(start) & mask;
+        if fired != 0 || deadline.is_some_and(|d| Instant::now() >= d) {
+            return Ok(Some(fired));
+        }
+        // Lines toggled back without counters to show it: wait again.
+    }
+}
+
+// Change any subset of the settings reported by serial_get_config (except
+// effectiveBaudRate) on an open port, given as a JSON object. Unknown keys or
+// invalid values fail with ERR_INVALID_CONFIG before anything is changed.
+#[no_mangle]
+pub extern "C" fn serial_set_config(h: u64, json: *const c_char) -> c_int {
+    if json.is_null() {
+        return invalid_arg("set_config", "null config");
+    }
+    let update = match unsafe { CStr::from_ptr(json) }.to_str() {
+        Ok(s) => config::ConfigUpdate::parse(s),
+        Err(e) => return invalid_arg("set_config", &e.to_string()),
+    };
+    with_state(h, Half::Control, "set_config", |state| {
+        let update = update?;
+        update.apply(&mut state.control.lock().unwrap(), |baud| {
+            state.requested_baud.store(baud, Ordering::Relaxed)
+        })?;
+        let mut policy = state.read_policy.lock().unwrap();
+        *policy = update.read_policy(*policy);
+        Ok(0)
+    })
+    .unwrap_or_else(|code| code)
+}
+
+// The settings in effect, as JSON with the keys of serial_set_config plus
+// effectiveBaudRate (the rate the driver reports, which can differ from the
+// requested baudRate), or null on error. Free with `serial_free_cstr`.
+#[no_mangle]
+pub extern "C" fn serial_get_config(h: u64) -> *mut c_char {
+    let res = with_state(h, Half::Control, "get_config", |state| {
+        let requested = state.requested_baud.load(Ordering::Relaxed);
+        let policy = *state.read_policy.lock().unwrap();
+        let config = config::Config::read(&state.control.lock().unwrap(), requested, policy)?;
+        serde_json::to_string(&config).map_err(|e| SerialError::new(ErrorKind::Io, e.to_string()))
+    });
+    match res {
+        Ok(json) => json_cstr(json),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
 // Purge input/output buffers
 #[no_mangle]
 pub extern "C" fn serial_flush(h: u64, flush_in: c_int, flush_out: c_int) -> c_int {
-    with_state_mut(h, |state| {
+    with_state(h, Half::Control, "flush", |state| {
         use serialport::ClearBuffer;
+        let port = &mut state.control.lock().unwrap();
         if flush_in != 0 && flush_out != 0 {
-            state.port.clear(ClearBuffer::All).map_err(set_err)?;
+            port.clear(ClearBuffer::All)?;
         } else if flush_in != 0 {
-            state.port.clear(ClearBuffer::Input).map_err(set_err)?;
+            port.clear(ClearBuffer::Input)?;
         } else if flush_out != 0 {
-            state.port.clear(ClearBuffer::Output).map_err(set_err)?;
+            port.clear(ClearBuffer::Output)?;
+        }
+        if flush_in != 0 {
+            // Bytes the reactor or a peek already pulled off the device.
+            if let Some(ring) = state.rx.lock().unwrap().as_mut() {
+                ring.clear();
+            }
+            state.read_ahead.lock().unwrap().clear();
         }
         Ok(0)
     })
     .unwrap_or_else(|code| code)
 }
 
+const TCFLOW_SUSPEND_OUTPUT: c_int = 0;
+const TCFLOW_RESUME_OUTPUT: c_int = 1;
+const TCFLOW_SEND_XOFF: c_int = 2;
+const TCFLOW_SEND_XON: c_int = 3;
+
+// tcflow(3): suspend/resume our output, or send the XOFF/XON character now,
+// ahead of anything queued. Unix only.
+#[no_mangle]
+pub extern "C" fn serial_tcflow(h: u64, action: c_int) -> c_int {
+    if !(TCFLOW_SUSPEND_OUTPUT..=TCFLOW_SEND_XON).contains(&action) {
+        return invalid_arg("tcflow", &format!("unknown action {action}"));
+    }
+    // The libc values differ between platforms.
+    #[cfg(unix)]
+    let action = match action {
+        TCFLOW_SUSPEND_OUTPUT => libc::TCOOFF,
+        TCFLOW_RESUME_OUTPUT => libc::TCOON,
+        TCFLOW_SEND_XOFF => libc::TCIOFF,
+        _ => libc::TCION,
+    };
+    with_port(h, Half::Control, "tcflow", |port| {
+        #[cfg(unix)]
+        {
+            sys::tcflow(port, action)?;
+            Ok(0)
+        }
+        #[cfg(windows)]
+        {
+            let _ = (port, action);
+            Err(SerialError::new(
+                ErrorKind::Unsupported,
+                "tcflow is not available on Windows",
+            ))
+        }
+    })
+    .unwrap_or_else(|code| code)
+}
+
 // Best-effort drain: wait until bytes_to_write() becomes 0 (with an upper bound)
 #[no_mangle]
 pub extern "C" fn serial_drain(h: u64) -> c_int {
+    with_state(h, Half::Control, "drain", |state| {
+        wait_output_empty(state)?;
+        Ok(0)
+    })
+    .unwrap_or_else(|code| code)
+}
+
+// The port is only locked while polling, not across the sleeps.
+fn wait_output_empty(state: &PortState) -> Result<(), SerialError> {
     const MAX_WAIT: Duration = Duration::from_secs(10);
     const SLEEP: Duration = Duration::from_millis(5);
 
     let start = Instant::now();
     loop {
-        let left = {
-            let res = with_state_mut(h, |state| match state.port.bytes_to_write() {
-                Ok(n) => Ok(n),
-                Err(e) => Err(set_err(e)),
-            });
-            match res {
-                Ok(n) => n,
-                Err(code) => return code,
-            }
-        };
+        let left = state.control.lock().unwrap().bytes_to_write()?;
         if left == 0 {
-            return 0;
+            return Ok(());
         }
         if start.elapsed() > MAX_WAIT {
-            return set_err("drain timeout");
+            return Err(SerialError::new(ErrorKind::TimedOut, "drain timeout"));
         }
         thread::sleep(SLEEP);
     }
 }
 
+// Sleep until `deadline`, spinning through the last stretch: the OS timer
+// alone overshoots by tens of microseconds on Linux and ~15 ms on Windows.
+fn sleep_until(deadline: Instant) {
+    const SPIN: Duration = if cfg!(windows) {
+        Duration::from_millis(16)
+    } else {
+        Duration::from_millis(1)
+    };
+    let now = Instant::now();
+    if deadline > now + SPIN {
+        thread::sleep(deadline - now - SPIN);
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+// Longest break or mark-after-break serial_send_break accepts (10 s).
+const MAX_BREAK_US: u32 = 10_000_000;
+
+// Send queued output, then hold break for `break_us` and mark for `mab_us`
+// (mark-after-break, may be 0) before returning; either above MAX_BREAK_US is
+// ERR_INVALID_CONFIG. Timed here rather than with two serial_set_lines calls,
+// whose spacing depends on the JS round-trip.
+#[no_mangle]
+pub extern "C" fn serial_send_break(h: u64, break_us: u32, mab_us: u32) -> c_int {
+    with_state(h, Half::Control, "send_break", |state| {
+        if break_us == 0 {
+            return Err(SerialError::new(
+                ErrorKind::InvalidConfig,
+                "break duration must be positive",
+            ));
+        }
+        if break_us.max(mab_us) > MAX_BREAK_US {
+            return Err(SerialError::new(
+                ErrorKind::InvalidConfig,
+                format!("break and mark-after-break are limited to {MAX_BREAK_US} us"),
+            ));
+        }
+        wait_output_empty(state)?;
+        // The control port is not held while sleeping: the reactor samples
+        // modem lines through it for every port.
+        {
+            let port = &mut *state.control.lock().unwrap();
+            // The queue is empty; this waits out the UART's own FIFO.
+            #[cfg(unix)]
+            sys::tcdrain(port)?;
+            port.set_break()?;
+        }
+        sleep_until(Instant::now() + Duration::from_micros(break_us.into()));
+        state.control.lock().unwrap().clear_break()?;
+        sleep_until(Instant::now() + Duration::from_micros(mab_us.into()));
+        Ok(0)
+    })
+    .unwrap_or_else(|code| code)
+}
+
 #[no_mangle]
 pub extern "C" fn serial_list_ports_len() -> usize {
     match serialport::available_ports() {
@@ -323,7 +1807,7 @@ pub extern "C" fn serial_list_ports_fill(out_ptrs: *mut *mut c_char, cap: usize)
     let ports = match serialport::available_ports() {
         Ok(p) => p,
         Err(e) => {
-            set_err(e);
+            set_err("list_ports", None, e);
             return 0;
         }
     };
@@ -332,12 +1816,11 @@ pub extern "C" fn serial_list_ports_fill(out_ptrs: *mut *mut c_char, cap: usize)
         let json = match port_to_json(&p) {
             Ok(s) => s,
             Err(e) => {
-                set_err(e);
+                set_err("list_ports", None, SerialError::new(ErrorKind::Io, e));
                 "{}".to_string()
             }
         };
-        let cstr = CString::new(json).unwrap_or_else(|_| CString::new("{}").unwrap());
-        let raw = cstr.into_raw();
+        let raw = json_cstr(json);
         unsafe {
             *out_ptrs.add(i) = raw;
         }
@@ -379,4 +1862,28 @@ pub extern "C" fn serial_free_cstr(p: *mut c_char) {
     }
 }
 
-// Future work: modem event waiting and explicit cancel APIs
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimiter_straddles_reads() {
+        let mut search = DelimiterSearch::new(b"\r\n");
+        let mut data = b"abc\r".to_vec();
+        assert_eq!(search.find(&data), None);
+        data.extend(b"\nrest");
+        assert_eq!(search.find(&data), Some(5));
+        assert_eq!(&data[5..], b"rest");
+    }
+
+    #[test]
+    fn delimiter_in_first_read() {
+        let mut search = DelimiterSearch::new(b"\n");
+        assert_eq!(search.find(b"a\nb\n"), Some(2));
+        let mut search = DelimiterSearch::new(b"END");
+        assert_eq!(search.find(b"E"), None);
+        assert_eq!(search.find(b"EN"), None);
+        assert_eq!(search.find(b"ENx"), None);
+        assert_eq!(search.find(b"ENxEND"), Some(6));
+    }
+}
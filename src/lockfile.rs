@@ -0,0 +1,192 @@
+//! UUCP-style `LCK..<device>` lock files, as used by minicom, picocom and
+//! ModemManager (Unix)
+use crate::error::{ErrorKind, SerialError};
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_DIR: &str = "/var/lock";
+
+/// A lock file owned by this process; removed on drop.
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Create the lock for `device` in `dir`, replacing a stale one left by a
+    /// process that no longer exists.
+    pub fn acquire(dir: &Path, device: &str) -> Result<Self, SerialError> {
+        // Lock the real device, so /dev/serial/by-id/... and /dev/ttyUSB0
+        // agree on the name.
+        let real = fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+        let path = dir.join(lock_name(&real)?);
+        // One retry: after removing a stale lock, another process may win the
+        // race to create it.
+        for _ in 0..2 {
+            match create(&path) {
+                Ok(()) => return Ok(LockFile { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            match owner(&path) {
+                Some(pid) if pid_alive(pid) => {
+                    return Err(SerialError::new(
+                        ErrorKind::Busy,
+                        format!("port is locked by process {pid} ({})", path.display()),
+                    ))
+                }
+                _ => match fs::remove_file(&path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                },
+            }
+        }
+        Err(SerialError::new(
+            ErrorKind::Busy,
+            format!("port lock {} is contended", path.display()),
+        ))
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Leave it alone if someone else has since taken it over.
+        if owner(&self.path) == Some(std::process::id() as libc::pid_t) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// `LCK..` plus the device's path below /dev with `/` turned into `_`:
+// /dev/ttyUSB0 gives the usual LCK..ttyUSB0, /dev/pts/3 gives LCK..pts_3
+// rather than a LCK..3 that /dev/foo/3 would share. Elsewhere, the file name.
+fn lock_name(device: &Path) -> Result<String, SerialError> {
+    let name = match device.strip_prefix("/dev") {
+        Ok(rel) if rel.components().next().is_some() => rel.to_string_lossy().replace('/', "_"),
+        _ => device
+            .file_name()
+            .ok_or_else(|| SerialError::new(ErrorKind::InvalidConfig, "path has no file name"))?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    Ok(format!("LCK..{name}"))
+}
+
+// HDB UUCP format: the PID as ten right-aligned ASCII digits and a newline.
+fn create(path: &Path) -> io::Result<()> {
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o644)
+        .open(path)?;
+    let written = f.write_all(format!("{:>10}\n", std::process::id()).as_bytes());
+    if written.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    written
+}
+
+// PID recorded in an existing lock, if it can be read.
+fn owner(path: &Path) -> Option<libc::pid_t> {
+    let bytes = fs::read(path).ok()?;
+    // Old-style binary locks hold a native 4-byte PID.
+    if bytes.len() == 4 {
+        return Some(libc::pid_t::from_ne_bytes(bytes.try_into().ok()?));
+    }
+    std::str::from_utf8(&bytes).ok()?.trim().parse().ok()
+}
+
+fn pid_alive(pid: libc::pid_t) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    // EPERM: it exists but belongs to another user.
+    let r = unsafe { libc::kill(pid, 0) };
+    r == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh lock directory per test, removed afterwards.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("lockfile-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn lock(&self) -> PathBuf {
+            self.0.join("LCK..ttyTEST0")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const DEVICE: &str = "/dev/ttyTEST0";
+
+    #[test]
+    fn creates_lock_with_our_pid() {
+        let dir = TempDir::new("create");
+        let _lock = LockFile::acquire(&dir.0, DEVICE).unwrap();
+        let text = fs::read_to_string(dir.lock()).unwrap();
+        assert_eq!(text, format!("{:>10}\n", std::process::id()));
+    }
+
+    #[test]
+    fn replaces_stale_lock() {
+        let dir = TempDir::new("stale");
+        // Above the kernel's PID limit, so no such process.
+        fs::write(dir.lock(), format!("{:>10}\n", 999_999_999)).unwrap();
+        let _lock = LockFile::acquire(&dir.0, DEVICE).unwrap();
+        assert_eq!(owner(&dir.lock()), Some(std::process::id() as libc::pid_t));
+    }
+
+    #[test]
+    fn live_lock_is_busy() {
+        let dir = TempDir::new("live");
+        fs::write(dir.lock(), format!("{:>10}\n", 1)).unwrap();
+        let err = LockFile::acquire(&dir.0, DEVICE).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Busy);
+        assert_eq!(owner(&dir.lock()), Some(1));
+    }
+
+    #[test]
+    fn removed_on_drop() {
+        let dir = TempDir::new("drop");
+        drop(LockFile::acquire(&dir.0, DEVICE).unwrap());
+        assert!(!dir.lock().exists());
+    }
+
+    #[test]
+    fn parses_owner() {
+        let dir = TempDir::new("owner");
+        let path = dir.lock();
+        fs::write(&path, "      4321\n").unwrap();
+        assert_eq!(owner(&path), Some(4321));
+        fs::write(&path, 4321i32.to_ne_bytes()).unwrap();
+        assert_eq!(owner(&path), Some(4321));
+        fs::write(&path, "garbage\n").unwrap();
+        assert_eq!(owner(&path), None);
+    }
+
+    #[test]
+    fn names_follow_the_path_below_dev() {
+        let name = |p: &str| lock_name(Path::new(p)).unwrap();
+        assert_eq!(name("/dev/ttyUSB0"), "LCK..ttyUSB0");
+        assert_eq!(name("/dev/pts/3"), "LCK..pts_3");
+        assert_eq!(name("/tmp/ttyV0"), "LCK..ttyV0");
+    }
+}
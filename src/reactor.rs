@@ -0,0 +1,316 @@
+//! Background epoll thread that drains registered ports into their receive
+//! rings or data callbacks, so reads return without tying up a Deno FFI
+//! worker thread (Linux)
+use crate::{
+    error::{ErrorKind, ErrorRecord, SerialError},
+    events::{self, Snapshot},
+    PortState,
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    io,
+    os::unix::io::RawFd,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+// `kind` values passed to a data callback.
+pub const CB_DATA: u32 = 0;
+pub const CB_EVENT: u32 = 1;
+pub const CB_CLOSED: u32 = 2;
+
+/// `fn(user_data, kind, value, data, len)`:
+/// - CB_DATA: `data`/`len` is the received chunk, valid only during the call
+/// - CB_EVENT: `value` holds the fired event bits (as in serial_wait_event)
+/// - CB_CLOSED: last call; `value` is 0 when unregistered or closed, else the
+///   negative error code that ended the stream
+pub type DataCallback =
+    extern "C" fn(user_data: *mut c_void, kind: u32, value: i32, data: *const u8, len: usize);
+
+#[derive(Clone, Copy)]
+pub struct Callback {
+    func: DataCallback,
+    // Opaque to us; kept as an integer so the callback stays Send.
+    user_data: usize,
+}
+
+impl Callback {
+    pub fn new(func: DataCallback, user_data: *mut c_void) -> Self {
+        Callback {
+            func,
+            user_data: user_data as usize,
+        }
+    }
+
+    fn call(&self, kind: u32, value: i32, data: &[u8]) {
+        (self.func)(
+            self.user_data as *mut c_void,
+            kind,
+            value,
+            data.as_ptr(),
+            data.len(),
+        );
+    }
+}
+
+// epoll token of the wake-up eventfd. Handle 0 is never issued.
+const WAKE_TOKEN: u64 = 0;
+
+struct Reactor {
+    epoll: RawFd,
+    wake_fd: RawFd,
+    // Keyed by handle rather than fd: a stale event for a port that was just
+    // deregistered (and whose fd may be reused) finds nothing and is dropped.
+    ports: Mutex<HashMap<u64, Arc<PortState>>>,
+    // Callbacks replaced or unregistered from other threads, waiting for their
+    // CB_CLOSED call. Only the reactor thread invokes callbacks, so that call
+    // comes strictly after any call already in progress.
+    retired: Mutex<Vec<Callback>>,
+}
+
+static REACTOR: Lazy<Result<&'static Reactor, i32>> = Lazy::new(|| {
+    let errno = || io::Error::last_os_error().raw_os_error().unwrap_or(0);
+    let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    if epoll < 0 {
+        return Err(errno());
+    }
+    let wake_fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if wake_fd < 0 {
+        return Err(errno());
+    }
+    let mut ev = libc::epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: WAKE_TOKEN,
+    };
+    if unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, wake_fd, &mut ev) } < 0 {
+        return Err(errno());
+    }
+    let reactor: &'static Reactor = Box::leak(Box::new(Reactor {
+        epoll,
+        wake_fd,
+        ports: Mutex::new(HashMap::new()),
+        retired: Mutex::new(Vec::new()),
+    }));
+    thread::Builder::new()
+        .name("deno-serial-reactor".into())
+        .spawn(move || reactor.run())
+        .map_err(|e| e.raw_os_error().unwrap_or(0))?;
+    Ok(reactor)
+});
+
+fn reactor() -> Result<&'static Reactor, SerialError> {
+    REACTOR
+        .as_ref()
+        .copied()
+        .map_err(|&errno| io::Error::from_raw_os_error(errno).into())
+}
+
+/// Start draining `state` into its receive ring or callback under handle `h`.
+pub fn register(h: u64, state: &Arc<PortState>) -> Result<(), SerialError> {
+    let r = reactor()?;
+    let mut ports = r.ports.lock().unwrap();
+    if ports.contains_key(&h) {
+        return Ok(());
+    }
+    let mut ev = libc::epoll_event {
+        events: libc::EPOLLIN as u32,
+        u64: h,
+    };
+    if unsafe { libc::epoll_ctl(r.epoll, libc::EPOLL_CTL_ADD, state.rx_fd, &mut ev) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    ports.insert(h, Arc::clone(state));
+    Ok(())
+}
+
+/// Stop draining handle `h` and retire its callback, if any. Harmless if it
+/// was never registered.
+pub fn deregister(h: u64) {
+    let Ok(r) = REACTOR.as_ref() else { return };
+    if let Some(state) = r.ports.lock().unwrap().remove(&h) {
+        unsafe {
+            libc::epoll_ctl(
+                r.epoll,
+                libc::EPOLL_CTL_DEL,
+                state.rx_fd,
+                std::ptr::null_mut(),
+            );
+        }
+        if let Some(cb) = state.callback.lock().unwrap().take() {
+            r.retire(cb);
+        }
+    }
+}
+
+/// Install (or with `None`, remove) the data callback of handle `h`. A
+/// replaced callback gets its CB_CLOSED call from the reactor thread.
+pub fn set_callback(
+    h: u64,
+    state: &Arc<PortState>,
+    cb: Option<Callback>,
+) -> Result<(), SerialError> {
+    let r = reactor()?;
+    if cb.is_some() {
+        register(h, state)?;
+    }
+    let old = std::mem::replace(&mut *state.callback.lock().unwrap(), cb);
+    if let Some(old) = old {
+        r.retire(old);
+    }
+    // Let the thread start (or stop) sampling modem lines.
+    r.wake();
+    Ok(())
+}
+
+impl Reactor {
+    fn wake(&self) {
+        let one = 1u64;
+        unsafe {
+            libc::write(self.wake_fd, &one as *const u64 as *const c_void, 8);
+        }
+    }
+
+    fn retire(&self, cb: Callback) {
+        self.retired.lock().unwrap().push(cb);
+        self.wake();
+    }
+
+    fn run(&self) {
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 64];
+        let mut buf = vec![0u8; 16 * 1024];
+        // Last modem-line sample of each port with a callback.
+        let mut lines: HashMap<u64, Snapshot> = HashMap::new();
+        loop {
+            let subscribed: Vec<(u64, Arc<PortState>)> = self
+                .ports
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, s)| s.callback.lock().unwrap().is_some())
+                .map(|(&h, s)| (h, Arc::clone(s)))
+                .collect();
+            lines.retain(|h, _| subscribed.iter().any(|(s, _)| s == h));
+            // Modem lines have no fd to wait on, so sample them while anyone
+            // is subscribed.
+            let timeout = if subscribed.is_empty() {
+                -1
+            } else {
+                events::POLL_INTERVAL.as_millis() as libc::c_int
+            };
+
+            let n = unsafe {
+                libc::epoll_wait(
+                    self.epoll,
+                    events.as_mut_ptr(),
+                    events.len() as i32,
+                    timeout,
+                )
+            };
+            for ev in &events[..n.max(0) as usize] {
+                let h = ev.u64;
+                if h == WAKE_TOKEN {
+                    let mut count = 0u64;
+                    unsafe {
+                        libc::read(self.wake_fd, &mut count as *mut u64 as *mut c_void, 8);
+                    }
+                    continue;
+                }
+                let state = match self.ports.lock().unwrap().get(&h) {
+                    Some(s) => Arc::clone(s),
+                    None => continue,
+                };
+                if let Err(e) = drain(&state, &mut buf) {
+                    let code = e.kind.code();
+                    // Callback users have no failing read to report it.
+                    *state.read_error.lock().unwrap() =
+                        Some(ErrorRecord::new("read", Some(&state.path), e.clone()));
+                    if let Some(ring) = state.rx.lock().unwrap().as_mut() {
+                        ring.set_error(e);
+                    }
+                    let cb = state.callback.lock().unwrap().take();
+                    if let Some(cb) = cb {
+                        cb.call(CB_CLOSED, code, &[]);
+                    }
+                    // Level-triggered: stop watching a dead fd.
+                    deregister(h);
+                }
+            }
+
+            for (h, state) in &subscribed {
+                let cb = *state.callback.lock().unwrap();
+                if let Some(cb) = cb {
+                    hand_over(state, &cb);
+                }
+                let Ok(now) = Snapshot::take(&mut state.control.lock().unwrap()) else {
+                    // e.g. a pty, which has no modem lines
+                    continue;
+                };
+                let fired = lines
+                    .insert(*h, now)
+                    .map_or(0, |before| now.events_since(&before));
+                if fired == 0 {
+                    continue;
+                }
+                let cb = *state.callback.lock().unwrap();
+                if let Some(cb) = cb {
+                    cb.call(CB_EVENT, fired as i32, &[]);
+                }
+            }
+
+            let retired = std::mem::take(&mut *self.retired.lock().unwrap());
+            for cb in retired {
+                cb.call(CB_CLOSED, 0, &[]);
+            }
+        }
+    }
+}
+
+// Pass what the receive ring still holds to a callback set since, ahead of
+// anything newer.
+fn hand_over(state: &PortState, cb: &Callback) {
+    let held = match state.rx.lock().unwrap().as_mut() {
+        Some(ring) if ring.len() > 0 => {
+            let mut held = vec![0; ring.len()];
+            ring.pop(&mut held);
+            held
+        }
+        _ => return,
+    };
+    cb.call(CB_DATA, 0, &held);
+}
+
+// Read everything the port has pending into its callback or ring.
+fn drain(state: &PortState, buf: &mut [u8]) -> Result<(), SerialError> {
+    loop {
+        let n = unsafe { libc::read(state.rx_fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        if n > 0 {
+            let arrived = crate::monotonic_ns();
+            let chunk = &buf[..n as usize];
+            // Copy the callback out so it is not called under the lock.
+            let cb = *state.callback.lock().unwrap();
+            match cb {
+                Some(cb) => {
+                    hand_over(state, &cb);
+                    cb.call(CB_DATA, 0, chunk);
+                }
+                None => {
+                    if let Some(ring) = state.rx.lock().unwrap().as_mut() {
+                        ring.push(chunk, arrived);
+                    }
+                }
+            }
+            continue;
+        }
+        if n == 0 {
+            return Err(SerialError::new(ErrorKind::Disconnected, "end of stream"));
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::WouldBlock => return Ok(()),
+            io::ErrorKind::Interrupted => continue,
+            _ => return Err(e.into()),
+        }
+    }
+}
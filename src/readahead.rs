@@ -0,0 +1,101 @@
+//! Bytes received but not yet handed out: kept back by serial_read_until,
+//! pushed back by serial_unread, or pulled in by serial_peek
+use std::collections::VecDeque;
+
+#[derive(Default)]
+pub struct ReadAhead {
+    data: VecDeque<u8>,
+}
+
+impl ReadAhead {
+    /// Append bytes just received.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+    }
+
+    /// Put `bytes` back in front, to be read again first.
+    pub fn unread(&mut self, bytes: &[u8]) {
+        for &b in bytes.iter().rev() {
+            self.data.push_front(b);
+        }
+    }
+
+    pub fn peek(&self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.data.len());
+        for (dst, src) in out.iter_mut().zip(self.data.range(..n)) {
+            *dst = *src;
+        }
+        n
+    }
+
+    pub fn pop(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.data.len());
+        for (dst, src) in out.iter_mut().zip(self.data.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    /// Drop up to `n` bytes; returns how many were dropped.
+    pub fn consume(&mut self, n: usize) -> usize {
+        let n = n.min(self.data.len());
+        self.data.drain(..n);
+        n
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(ahead: &mut ReadAhead) -> Vec<u8> {
+        let mut out = vec![0; ahead.len()];
+        ahead.pop(&mut out);
+        out
+    }
+
+    #[test]
+    fn unread_goes_first_in_order() {
+        let mut ahead = ReadAhead::default();
+        ahead.push(b"cd");
+        ahead.unread(b"ab");
+        ahead.push(b"ef");
+        ahead.unread(b"0");
+        assert_eq!(take(&mut ahead), b"0abcdef");
+    }
+
+    #[test]
+    fn peek_leaves_bytes() {
+        let mut ahead = ReadAhead::default();
+        ahead.push(b"abc");
+        let mut out = [0; 2];
+        assert_eq!(ahead.peek(&mut out), 2);
+        assert_eq!(&out, b"ab");
+        assert_eq!(ahead.len(), 3);
+        assert_eq!(ahead.pop(&mut out), 2);
+        assert_eq!(&out, b"ab");
+        let mut out = [0; 4];
+        assert_eq!(ahead.peek(&mut out), 1);
+        assert_eq!(ahead.pop(&mut out), 1);
+        assert_eq!(&out[..1], b"c");
+        assert_eq!(ahead.pop(&mut out), 0);
+    }
+
+    #[test]
+    fn consume_clamps() {
+        let mut ahead = ReadAhead::default();
+        ahead.push(b"abcd");
+        assert_eq!(ahead.consume(1), 1);
+        assert_eq!(ahead.consume(10), 3);
+        assert_eq!(ahead.consume(1), 0);
+        assert_eq!(ahead.len(), 0);
+    }
+}
@@ -0,0 +1,64 @@
+//! Termios and modem-line snapshot taken before open and put back on close
+//! (Linux)
+use crate::termios;
+use std::{
+    ffi::CString,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::Once,
+};
+
+/// Device state as it was before we configured it.
+pub struct Saved {
+    termios: libc::termios2,
+    // TIOCM_* bits; None where the device has no modem lines (e.g. a pty).
+    lines: Option<libc::c_int>,
+}
+
+/// Read the settings of `path` through a separate descriptor, before
+/// serialport makes the port raw. Keep the returned fd open until the port
+/// itself is open: if it were the last close, HUPCL would hang up the lines.
+pub fn take(path: &str) -> io::Result<(OwnedFd, Saved)> {
+    let c_path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let fd = unsafe {
+        libc::open(
+            c_path.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let termios = termios::get(fd.as_raw_fd())?;
+    let mut lines: libc::c_int = 0;
+    let lines =
+        (unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCMGET, &mut lines) } == 0).then_some(lines);
+    Ok((fd, Saved { termios, lines }))
+}
+
+impl Saved {
+    /// Put the termios settings and DTR/RTS back on the device behind `fd`.
+    pub fn restore(&self, fd: RawFd) -> io::Result<()> {
+        termios::set(fd, &self.termios)?;
+        if let Some(lines) = self.lines {
+            let mask = libc::TIOCM_DTR | libc::TIOCM_RTS;
+            let (on, off) = (lines & mask, !lines & mask);
+            if unsafe { libc::ioctl(fd, libc::TIOCMBIS, &on) } < 0
+                || unsafe { libc::ioctl(fd, libc::TIOCMBIC, &off) } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Arrange for `restore_all` to run when the process exits or the library is
+/// unloaded (glibc runs a shared object's atexit handlers on dlclose).
+pub fn restore_at_exit() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| unsafe {
+        libc::atexit(crate::restore_all);
+    });
+}
@@ -0,0 +1,218 @@
+//! Bounded receive buffer filled by the background reactor
+use crate::error::SerialError;
+use std::collections::VecDeque;
+
+/// Largest capacity serial_set_rx_buffer accepts.
+pub const MAX_CAPACITY: usize = 64 << 20;
+
+pub struct RxRing {
+    data: VecDeque<u8>,
+    capacity: usize,
+    // Stream position of data[0], counting every byte ever pushed.
+    head: u64,
+    // Arrival time of each pushed chunk, oldest first. While there is data,
+    // the first one dates data[0] (its chunk may have been partly consumed).
+    stamps: VecDeque<Stamp>,
+    // Bytes discarded because the buffer was full (oldest data goes first).
+    overflow: u64,
+    // End of stream / device error seen by the reactor; reported to readers
+    // once the buffered data has been consumed.
+    error: Option<SerialError>,
+}
+
+struct Stamp {
+    // Stream position of the chunk's first byte.
+    at: u64,
+    ns: u64,
+}
+
+impl RxRing {
+    pub fn new(capacity: usize) -> Self {
+        RxRing {
+            // Grows with the data rather than reserving `capacity` up front.
+            data: VecDeque::new(),
+            capacity,
+            head: 0,
+            stamps: VecDeque::new(),
+            overflow: 0,
+            error: None,
+        }
+    }
+
+    /// Append a chunk that arrived at `ns` (monotonic_ns).
+    pub fn push(&mut self, bytes: &[u8], ns: u64) {
+        if bytes.is_empty() {
+            return;
+        }
+        // Only the newest `capacity` bytes of `bytes` can survive.
+        let skip = bytes.len().saturating_sub(self.capacity);
+        let bytes = &bytes[skip..];
+        let excess = (self.data.len() + bytes.len()).saturating_sub(self.capacity);
+        self.discard(excess);
+        self.stamps.push_back(Stamp {
+            at: self.head + self.data.len() as u64,
+            ns,
+        });
+        self.data.extend(bytes);
+        self.overflow += (skip + excess) as u64;
+    }
+
+    pub fn pop(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.data.len());
+        for (dst, src) in out.iter_mut().zip(self.data.drain(..n)) {
+            *dst = src;
+        }
+        self.advance(n);
+        n
+    }
+
+    /// Like `pop`, also returning when the first byte's chunk arrived. With
+    /// `split`, stops at the end of that chunk.
+    pub fn pop_stamped(&mut self, out: &mut [u8], split: bool) -> (usize, u64) {
+        let Some(first) = self.stamps.front() else {
+            return (0, 0);
+        };
+        let ns = first.ns;
+        let mut n = out.len();
+        if split {
+            if let Some(next) = self.stamps.get(1) {
+                n = n.min((next.at - self.head) as usize);
+            }
+        }
+        (self.pop(&mut out[..n]), ns)
+    }
+
+    pub fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.data.len().saturating_sub(capacity);
+        self.discard(excess);
+        self.overflow += excess as u64;
+    }
+
+    pub fn clear(&mut self) {
+        self.discard(self.data.len());
+    }
+
+    fn discard(&mut self, n: usize) {
+        self.data.drain(..n);
+        self.advance(n);
+    }
+
+    // Move the head past `n` bytes just removed from the front.
+    fn advance(&mut self, n: usize) {
+        self.head += n as u64;
+        if self.data.is_empty() {
+            self.stamps.clear();
+            return;
+        }
+        while self.stamps.get(1).is_some_and(|next| next.at <= self.head) {
+            self.stamps.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn overflow(&self) -> u64 {
+        self.overflow
+    }
+
+    pub fn set_error(&mut self, e: SerialError) {
+        self.error.get_or_insert(e);
+    }
+
+    pub fn error(&self) -> Option<&SerialError> {
+        self.error.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(ring: &mut RxRing) -> Vec<u8> {
+        let mut out = vec![0; ring.len()];
+        ring.pop(&mut out);
+        out
+    }
+
+    #[test]
+    fn overflow_drops_oldest() {
+        let mut ring = RxRing::new(4);
+        ring.push(b"abc", 1);
+        ring.push(b"def", 2);
+        assert_eq!(ring.overflow(), 2);
+        assert_eq!(drain(&mut ring), b"cdef");
+    }
+
+    #[test]
+    fn oversized_push_keeps_newest() {
+        let mut ring = RxRing::new(4);
+        ring.push(b"ab", 1);
+        ring.push(b"0123456789", 2);
+        assert_eq!(ring.overflow(), 8);
+        let mut out = [0; 8];
+        assert_eq!(ring.pop_stamped(&mut out, true), (4, 2));
+        assert_eq!(&out[..4], b"6789");
+    }
+
+    #[test]
+    fn split_follows_chunks() {
+        let mut ring = RxRing::new(16);
+        ring.push(b"abcd", 10);
+        ring.push(b"ef", 20);
+        ring.push(b"gh", 30);
+        let mut out = [0; 16];
+        // Part of the first chunk goes, the rest keeps its stamp.
+        assert_eq!(ring.pop(&mut out[..3]), 3);
+        assert_eq!(ring.pop_stamped(&mut out, true), (1, 10));
+        assert_eq!(&out[..1], b"d");
+        assert_eq!(ring.pop_stamped(&mut out[..1], true), (1, 20));
+        assert_eq!(ring.pop_stamped(&mut out, true), (1, 20));
+        assert_eq!(&out[..1], b"f");
+        // Without split the first byte's stamp covers the whole read.
+        ring.push(b"ij", 40);
+        assert_eq!(ring.pop_stamped(&mut out, false), (4, 30));
+        assert_eq!(&out[..4], b"ghij");
+        assert_eq!(ring.pop_stamped(&mut out, false), (0, 0));
+    }
+
+    #[test]
+    fn overflow_moves_stamps() {
+        let mut ring = RxRing::new(4);
+        ring.push(b"ab", 1);
+        ring.push(b"cd", 2);
+        ring.push(b"efg", 3);
+        // "d" survives from the second chunk.
+        let mut out = [0; 4];
+        assert_eq!(ring.pop_stamped(&mut out, true), (1, 2));
+        assert_eq!(ring.pop_stamped(&mut out, true), (3, 3));
+    }
+
+    #[test]
+    fn resize_shrinks_from_the_front() {
+        let mut ring = RxRing::new(8);
+        ring.push(b"abc", 1);
+        ring.push(b"def", 2);
+        ring.resize(2);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.overflow(), 4);
+        let mut out = [0; 4];
+        assert_eq!(ring.pop_stamped(&mut out, true), (2, 2));
+        assert_eq!(&out[..2], b"ef");
+        ring.push(b"xyz", 3);
+        assert_eq!(ring.overflow(), 5);
+        assert_eq!(drain(&mut ring), b"yz");
+    }
+
+    #[test]
+    fn clear_keeps_overflow() {
+        let mut ring = RxRing::new(2);
+        ring.push(b"abc", 1);
+        ring.clear();
+        assert_eq!(ring.len(), 0);
+        assert_eq!(ring.overflow(), 1);
+        assert_eq!(ring.pop_stamped(&mut [0; 2], false), (0, 0));
+    }
+}
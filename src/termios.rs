@@ -0,0 +1,77 @@
+//! Direct termios access for settings serialport has no enum for (Linux)
+use std::{io, os::unix::io::RawFd};
+
+pub fn get(fd: RawFd) -> io::Result<libc::termios2> {
+    let mut t: libc::termios2 = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TCGETS2, &mut t) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(t)
+}
+
+pub fn set(fd: RawFd, t: &libc::termios2) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, libc::TCSETS2, t) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Read-modify-write the port's termios.
+pub fn update(fd: RawFd, f: impl FnOnce(&mut libc::termios2)) -> io::Result<()> {
+    let mut t = get(fd)?;
+    f(&mut t);
+    set(fd, &t)
+}
+
+// <linux/serial.h>; libc has the ioctls but not the struct.
+#[repr(C)]
+struct SerialStruct {
+    type_: libc::c_int,
+    line: libc::c_int,
+    port: libc::c_uint,
+    irq: libc::c_int,
+    flags: libc::c_int,
+    xmit_fifo_size: libc::c_int,
+    custom_divisor: libc::c_int,
+    baud_base: libc::c_int,
+    close_delay: libc::c_ushort,
+    io_type: libc::c_char,
+    reserved_char: [libc::c_char; 1],
+    hub6: libc::c_int,
+    closing_wait: libc::c_ushort,
+    closing_wait2: libc::c_ushort,
+    iomem_base: *mut libc::c_uchar,
+    iomem_reg_shift: libc::c_ushort,
+    port_high: libc::c_uint,
+    iomap_base: libc::c_ulong,
+}
+
+const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+fn get_serial(fd: RawFd) -> io::Result<SerialStruct> {
+    let mut s: SerialStruct = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGSERIAL, &mut s) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(s)
+}
+
+/// Whether the driver flushes received data to the tty at once rather than
+/// batching it (for USB adapters, a 1 ms instead of a 16 ms latency timer).
+/// Fails on devices without serial_struct support, such as ptys.
+pub fn low_latency(fd: RawFd) -> io::Result<bool> {
+    Ok(get_serial(fd)?.flags & ASYNC_LOW_LATENCY != 0)
+}
+
+pub fn set_low_latency(fd: RawFd, on: bool) -> io::Result<()> {
+    let mut s = get_serial(fd)?;
+    if on {
+        s.flags |= ASYNC_LOW_LATENCY;
+    } else {
+        s.flags &= !ASYNC_LOW_LATENCY;
+    }
+    if unsafe { libc::ioctl(fd, libc::TIOCSSERIAL, &s) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
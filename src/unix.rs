@@ -0,0 +1,298 @@
+//! Unix I/O primitives: poll-based reads/writes that can be woken from another thread
+use crate::error::{ErrorKind, SerialError};
+use serialport::{SerialPort, TTYPort};
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Self-pipe used to wake a thread blocked in `poll` on a port.
+///
+/// A wake-up only reaches calls in flight (see `begin`). For those it is
+/// sticky: a call that has not reached `poll` yet returns there at once.
+pub struct Waker {
+    rx: RawFd,
+    tx: RawFd,
+    // Calls between `begin` and the drop of their guard.
+    in_flight: Mutex<usize>,
+}
+
+/// Keeps a call interruptible by `Waker::wake` until dropped.
+pub struct InFlight<'a>(&'a Waker);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut n = self.0.in_flight.lock().unwrap();
+        *n -= 1;
+        // Nobody left to interrupt: drop a wake-up no wait has consumed.
+        if *n == 0 {
+            self.0.reset();
+        }
+    }
+}
+
+impl Waker {
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [0 as RawFd; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let waker = Waker {
+            rx: fds[0],
+            tx: fds[1],
+            in_flight: Mutex::new(0),
+        };
+        for fd in fds {
+            unsafe {
+                let fl = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, fl | libc::O_NONBLOCK);
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+        Ok(waker)
+    }
+
+    /// Mark the start of a call that `wake` should interrupt.
+    pub fn begin(&self) -> InFlight<'_> {
+        *self.in_flight.lock().unwrap() += 1;
+        InFlight(self)
+    }
+
+    /// Interrupt the calls in flight; a no-op when there are none.
+    pub fn wake(&self) {
+        let n = self.in_flight.lock().unwrap();
+        if *n == 0 {
+            return;
+        }
+        // A full pipe already holds a pending wake-up, so EAGAIN is fine.
+        let b = 1u8;
+        unsafe {
+            libc::write(self.tx, &b as *const u8 as *const libc::c_void, 1);
+        }
+    }
+
+    /// Sleep for `dur` unless woken first; returns true if woken.
+    pub fn wait(&self, dur: Duration) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.rx,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let n = unsafe { libc::poll(&mut fd, 1, poll_timeout_ms(Some(dur))) };
+        if n > 0 {
+            self.reset();
+            return true;
+        }
+        false
+    }
+
+    fn reset(&self) {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.rx, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {
+        }
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.rx);
+            libc::close(self.tx);
+        }
+    }
+}
+
+/// Switch the port (and every clone sharing its open file description) to
+/// non-blocking mode, so a read/write after `poll` never blocks past a wake-up.
+pub fn set_nonblocking(port: &TTYPort) -> io::Result<()> {
+    let fd = port.as_raw_fd();
+    unsafe {
+        let fl = libc::fcntl(fd, libc::F_GETFL);
+        if fl < 0 || libc::fcntl(fd, libc::F_SETFL, fl | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Claim the port for this process: TIOCEXCL refuses further opens of the
+/// tty (except by root) and an advisory flock stops cooperating programs,
+/// root included.
+pub fn lock_exclusive(port: &TTYPort) -> Result<(), SerialError> {
+    let fd = port.as_raw_fd();
+    if unsafe { libc::ioctl(fd, libc::TIOCEXCL) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(e.into());
+        }
+        return Err(SerialError {
+            kind: ErrorKind::Busy,
+            errno: e.raw_os_error(),
+            message: "port is locked by another process".into(),
+        });
+    }
+    Ok(())
+}
+
+/// tcflow(3) with one of the TCOOFF/TCOON/TCIOFF/TCION actions.
+pub fn tcflow(port: &TTYPort, action: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::tcflow(port.as_raw_fd(), action) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Wait until everything written has left the transmitter. Only call it
+/// with the output queue already empty: it cannot be woken.
+pub fn tcdrain(port: &TTYPort) -> io::Result<()> {
+    if unsafe { libc::tcdrain(port.as_raw_fd()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn poll_timeout_ms(remaining: Option<Duration>) -> libc::c_int {
+    match remaining {
+        None => -1,
+        // Round up so a sub-millisecond remainder does not spin.
+        Some(d) => {
+            let ms = d.as_micros().div_ceil(1000);
+            ms.min(libc::c_int::MAX as u128) as libc::c_int
+        }
+    }
+}
+
+// ppoll on Linux, so short waits (inter-byte timeouts) are not rounded up to
+// a whole millisecond.
+#[cfg(target_os = "linux")]
+fn poll_fds(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> libc::c_int {
+    let ts = timeout.map(|d| libc::timespec {
+        tv_sec: d.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: d.subsec_nanos() as libc::c_long,
+    });
+    let ts_ptr = ts
+        .as_ref()
+        .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+    unsafe {
+        libc::ppoll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            ts_ptr,
+            std::ptr::null(),
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn poll_fds(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> libc::c_int {
+    unsafe {
+        libc::poll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            poll_timeout_ms(timeout),
+        )
+    }
+}
+
+/// Wait until `fd` has `events` pending. `None` waits forever.
+///
+/// Fails with `TimedOut` when the timeout expires and `Interrupted` when the
+/// waker fires.
+pub fn wait_fd(
+    fd: RawFd,
+    events: libc::c_short,
+    waker: &Waker,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let mut fds = [
+            libc::pollfd {
+                fd,
+                events,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: waker.rx,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let n = poll_fds(&mut fds, remaining);
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if fds[1].revents != 0 {
+            waker.reset();
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let rev = fds[0].revents;
+        if rev & (events | libc::POLLERR) != 0 {
+            // Let the following read/write report the actual error.
+            return Ok(());
+        }
+        if rev & libc::POLLNVAL != 0 {
+            return Err(io::Error::from_raw_os_error(libc::EBADF));
+        }
+        if rev & libc::POLLHUP != 0 {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "hang up"));
+        }
+        if n == 0 && remaining.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+    }
+}
+
+/// Read whatever is available once the port becomes readable.
+pub fn read(
+    port: &mut TTYPort,
+    buf: &mut [u8],
+    waker: &Waker,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let fd = port.as_raw_fd();
+    loop {
+        wait_fd(fd, libc::POLLIN, waker, timeout)?;
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => continue,
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Write as much of `buf` as the port accepts once it becomes writable,
+/// bounded by the port's timeout.
+pub fn write(port: &mut TTYPort, buf: &[u8], waker: &Waker) -> io::Result<usize> {
+    let fd = port.as_raw_fd();
+    let timeout = port.timeout();
+    loop {
+        wait_fd(fd, libc::POLLOUT, waker, Some(timeout))?;
+        let n = unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => continue,
+            _ => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Windows I/O primitives: reads/writes that can be cancelled from another thread
+use serialport::{COMPort, SerialPort};
+use std::{
+    io::{self, Read, Write},
+    os::windows::io::AsRawHandle,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use winapi::um::ioapiset::CancelIoEx;
+
+const ERROR_OPERATION_ABORTED: i32 = 995;
+
+// Long waits are split into slices so a wake-up that lands just before
+// ReadFile starts (and so is missed by CancelIoEx) is still seen promptly.
+const SLICE: Duration = Duration::from_secs(1);
+
+/// Wakes a thread blocked in `ReadFile`/`WriteFile` on a port half.
+///
+/// A wake-up only reaches calls in flight (see `begin`). For those it is
+/// sticky: a call that has not reached `ReadFile` yet returns at once.
+pub struct Waker {
+    pending: AtomicBool,
+    // Calls between `begin` and the drop of their guard.
+    in_flight: Mutex<usize>,
+    // Raw HANDLE of the port half to cancel, kept as an integer so the
+    // waker stays Send + Sync. It lives as long as the owning PortState.
+    handle: usize,
+}
+
+impl Waker {
+    pub fn new(port: &COMPort) -> Self {
+        Waker {
+            pending: AtomicBool::new(false),
+            in_flight: Mutex::new(0),
+            handle: port.as_raw_handle() as usize,
+        }
+    }
+
+    /// Mark the start of a call that `wake` should interrupt.
+    pub fn begin(&self) -> InFlight<'_> {
+        *self.in_flight.lock().unwrap() += 1;
+        InFlight(self)
+    }
+
+    /// Interrupt the calls in flight; a no-op when there are none.
+    pub fn wake(&self) {
+        let n = self.in_flight.lock().unwrap();
+        if *n == 0 {
+            return;
+        }
+        self.pending.store(true, Ordering::SeqCst);
+        unsafe {
+            CancelIoEx(self.handle as _, ptr::null_mut());
+        }
+    }
+
+    /// Sleep for `dur` unless woken first; returns true if woken.
+    pub fn wait(&self, dur: Duration) -> bool {
+        std::thread::sleep(dur);
+        self.take()
+    }
+
+    fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Keeps a call interruptible by `Waker::wake` until dropped.
+pub struct InFlight<'a>(&'a Waker);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut n = self.0.in_flight.lock().unwrap();
+        *n -= 1;
+        // Nobody left to interrupt: drop a wake-up no wait has consumed.
+        if *n == 0 {
+            self.0.take();
+        }
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "cancelled")
+}
+
+/// Read whatever arrives within `timeout`. `None` waits forever.
+///
+/// Fails with `TimedOut` when the timeout expires and `Interrupted` when the
+/// waker fires.
+pub fn read(
+    port: &mut COMPort,
+    buf: &mut [u8],
+    waker: &Waker,
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        if waker.take() {
+            return Err(cancelled());
+        }
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let slice = remaining.map_or(SLICE, |r| r.min(SLICE));
+        port.set_timeout(slice)?;
+        match port.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                if remaining.is_some_and(|r| r <= slice) {
+                    return Err(e);
+                }
+            }
+            Err(e) if e.raw_os_error() == Some(ERROR_OPERATION_ABORTED) => {
+                waker.take();
+                return Err(cancelled());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Write to the port, bounded by its (device-wide) COMMTIMEOUTS.
+pub fn write(port: &mut COMPort, buf: &[u8], waker: &Waker) -> io::Result<usize> {
+    if waker.take() {
+        return Err(cancelled());
+    }
+    match port.write(buf) {
+        Err(e) if e.raw_os_error() == Some(ERROR_OPERATION_ABORTED) => {
+            waker.take();
+            Err(cancelled())
+        }
+        res => res,
+    }
+}